@@ -0,0 +1,762 @@
+// This is a part of CSON-rust.
+// Written by Kang Seonghoon. See README.md for details.
+
+//! A reader for the CoffeeScript-style CSON, which is a different format from this crate's CSON.
+//!
+//! That format is a subset of CoffeeScript object literals, as used by the Atom editor and
+//! its ecosystem. This reader accepts:
+//!
+//! * Objects nested by the indentation, and `key: value` with bare or quoted keys.
+//! * `{...}` and `[...]` separated by commas or newlines.
+//! * Single- and double-quoted strings, including multi-line ones, and `'''`/`"""` block
+//!   strings whose common indentation is removed.
+//! * Numbers including `0x`, `0o` and `0b` prefixes, `true`/`yes`/`on`, `false`/`no`/`off`,
+//!   `null`, `NaN` and `Infinity` with an optional sign. Number keys are converted to
+//!   strings as JavaScript does, so `0x10: 1` has the key `"16"`.
+//! * `#` line comments and `###` block comments.
+//!
+//! Anything which CoffeeScript would execute (variables, function calls, string interpolation,
+//! operators, regular expressions and so on) is rejected with an error saying so.
+//! The result is `repr::Atom`, so it can be written back with `writer::Writer`.
+
+use std::str;
+use super::repr;
+use super::repr::Key;
+use super::reader::{ReaderResult, ReaderError, ErrorKind, Limit, Options, Position};
+
+/// Parses a CoffeeScript-style CSON document from bytes, which should be in UTF-8.
+/// A UTF-8 byte order mark is skipped.
+pub fn parse_document_from_buf(buf: &[u8]) -> ReaderResult<repr::Atom<'static>> {
+    match str::from_utf8(buf) {
+        Ok(text) => parse_document_from_str(text),
+        Err(e) => {
+            let pos = Position::from_offset(buf, e.valid_up_to());
+            Err(ReaderError { kind: ErrorKind::Syntax, cause: "invalid UTF-8 sequence".into(),
+                              ioerr: None, pos: Some(pos) })
+        }
+    }
+}
+
+/// Parses a CoffeeScript-style CSON document. A byte order mark is skipped.
+pub fn parse_document_from_str(text: &str) -> ReaderResult<repr::Atom<'static>> {
+    let start = if text.starts_with('\u{feff}') { 3 } else { 0 };
+    let mut parser = Parser { text: text, pos: start, depth: 0,
+                              max_depth: Options::default().max_depth };
+    parser.document()
+}
+
+fn is_ident_start(c: char) -> bool {
+    c == '$' || c == '_' || c.is_alphabetic()
+}
+
+fn is_ident_continue(c: char) -> bool {
+    c == '$' || c == '_' || c.is_alphanumeric()
+}
+
+#[derive(Clone)]
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str { &self.text[self.pos..] }
+    fn peek(&self) -> Option<char> { self.rest().chars().next() }
+    fn peek_nth(&self, n: usize) -> Option<char> { self.rest().chars().nth(n) }
+
+    /// Returns true if the next character satisfies `pred`.
+    fn peek_is<F: Fn(char) -> bool>(&self, pred: F) -> bool {
+        match self.peek() {
+            Some(c) => pred(c),
+            None => false,
+        }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek();
+        if let Some(c) = c { self.pos += c.len_utf8(); }
+        c
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) { self.pos += c.len_utf8(); true } else { false }
+    }
+
+    fn err_at<T, Cause: Into<String>>(&self, offset: usize, cause: Cause) -> ReaderResult<T> {
+        Err(ReaderError { kind: ErrorKind::Syntax, cause: cause.into().into(), ioerr: None,
+                          pos: Some(Position::from_offset(self.text.as_bytes(), offset)) })
+    }
+
+    fn err<T, Cause: Into<String>>(&self, cause: Cause) -> ReaderResult<T> {
+        self.err_at(self.pos, cause)
+    }
+
+    /// Reports a construct which CoffeeScript accepts but this reader does not.
+    fn unsupported<T>(&self, what: &str) -> ReaderResult<T> {
+        self.err(format!("{} is not supported, as it would have to be executed", what))
+    }
+
+    /// Calls `f` for a nested object or array, keeping the depth balanced even on errors.
+    fn nested<T, F>(&mut self, f: F) -> ReaderResult<T>
+        where F: FnOnce(&mut Parser<'a>) -> ReaderResult<T>
+    {
+        try!(self.enter());
+        let ret = f(self);
+        self.depth -= 1;
+        ret
+    }
+
+    fn enter(&mut self) -> ReaderResult<()> {
+        if self.depth >= self.max_depth {
+            return Err(ReaderError {
+                kind: ErrorKind::LimitExceeded(Limit::Depth),
+                cause: format!("exceeded the maximum nesting depth of {}", self.max_depth).into(),
+                ioerr: None, pos: Some(Position::from_offset(self.text.as_bytes(), self.pos)),
+            });
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn at_eol(&self) -> bool {
+        match self.peek() {
+            None | Some('\n') | Some('\r') => true,
+            _ => false,
+        }
+    }
+
+    fn skip_newline(&mut self) {
+        self.eat('\r');
+        self.eat('\n');
+    }
+
+    /// Returns the number of characters between the start of the line and the current position.
+    fn column(&self) -> usize {
+        let before = &self.text[..self.pos];
+        let start = before.rfind(&['\n', '\r'][..]).map_or(0, |i| i + 1);
+        before[start..].chars().count()
+    }
+
+    fn is_block_comment(&self) -> bool {
+        self.rest().starts_with("###") && self.peek_nth(3) != Some('#')
+    }
+
+    /// Skips a `###` block comment.
+    fn skip_block_comment(&mut self) -> ReaderResult<()> {
+        let start = self.pos;
+        self.pos += 3;
+        match self.rest().find("###") {
+            Some(end) => { self.pos += end + 3; Ok(()) }
+            None => self.err_at(start, "unterminated block comment"),
+        }
+    }
+
+    /// Skips spaces, tabs and comments in the current line.
+    /// A block comment may span multiple lines, though.
+    fn skip_inline(&mut self) -> ReaderResult<()> {
+        loop {
+            match self.peek() {
+                Some(' ') | Some('\t') => { self.pos += 1; }
+                Some('#') if self.is_block_comment() => { try!(self.skip_block_comment()); }
+                Some('#') => {
+                    while !self.at_eol() { self.bump(); }
+                }
+                _ => { return Ok(()); }
+            }
+        }
+    }
+
+    /// Skips every whitespace, newline and comment.
+    fn skip_all(&mut self) -> ReaderResult<()> {
+        loop {
+            try!(self.skip_inline());
+            if self.peek().is_none() || !self.at_eol() { return Ok(()); }
+            self.skip_newline();
+        }
+    }
+
+    /// Given the end of a line, finds the next line with anything other than comments.
+    /// Returns its indentation and the position of its first character, or `None` at the end.
+    fn next_line(&self) -> ReaderResult<Option<(usize, usize)>> {
+        let mut p = self.clone();
+        loop {
+            p.skip_newline();
+            let indent = p.rest().len() - p.rest().trim_left_matches(|c| c == ' ' ||
+                                                                       c == '\t').len();
+            p.pos += indent;
+            try!(p.skip_inline());
+            if p.peek().is_none() { return Ok(None); }
+            if !p.at_eol() {
+                // a block comment followed by something else leaves the column changed
+                let column = p.column();
+                return Ok(Some((if column > indent { column } else { indent }, p.pos)));
+            }
+        }
+    }
+
+    /// Returns true if `key:` starts at the current position.
+    fn starts_member(&self) -> bool {
+        let mut p = self.clone();
+        if p.key().is_err() { return false; }
+        if p.skip_inline().is_err() { return false; }
+        p.peek() == Some(':') && p.peek_nth(1) != Some(':')
+    }
+
+    fn document(&mut self) -> ReaderResult<repr::Atom<'static>> {
+        try!(self.skip_all());
+        if self.peek().is_none() { return self.err("expected document"); }
+        let value = if self.starts_member() {
+            let column = self.column();
+            repr::Object(try!(self.implicit_object(column)))
+        } else {
+            try!(self.value())
+        };
+        try!(self.skip_all());
+        if self.peek().is_some() { return self.err("expected end of file"); }
+        Ok(value)
+    }
+
+    /// Parses members at the same indentation `indent`, which starts at the current position.
+    fn implicit_object(&mut self, indent: usize) -> ReaderResult<repr::AtomObject<'static>> {
+        self.nested(|p| p.implicit_members(indent))
+    }
+
+    fn implicit_members(&mut self, indent: usize) -> ReaderResult<repr::AtomObject<'static>> {
+        let mut items = repr::AtomObject::new();
+        loop {
+            let key = try!(self.key());
+            try!(self.skip_inline());
+            if !self.eat(':') { return self.err("expected `:`"); }
+            let value = try!(self.member_value(indent));
+            items.insert(Key::new(key), value);
+
+            try!(self.skip_inline());
+            if self.eat(',') {
+                try!(self.skip_inline());
+                if !self.at_eol() {
+                    if !self.starts_member() { return self.err("expected a member after `,`"); }
+                    continue;
+                }
+            }
+            if !self.at_eol() || self.peek().is_none() { break; }
+            match try!(self.next_line()) {
+                Some((n, pos)) if n == indent => {
+                    let mut p = self.clone();
+                    p.pos = pos;
+                    if !p.starts_member() { break; }
+                    self.pos = pos;
+                }
+                Some((n, pos)) if n > indent => {
+                    return self.err_at(pos, "unexpected indentation");
+                }
+                _ => { break; }
+            }
+        }
+        Ok(items)
+    }
+
+    /// Parses a value after `key:` of a member in the object indented by `indent`.
+    fn member_value(&mut self, indent: usize) -> ReaderResult<repr::Atom<'static>> {
+        try!(self.skip_inline());
+        if self.at_eol() {
+            match try!(self.next_line()) {
+                Some((n, pos)) if n > indent => { self.pos = pos; }
+                _ => { return self.err("expected a value"); }
+            }
+        }
+        if self.starts_member() {
+            let column = self.column();
+            Ok(repr::Object(try!(self.implicit_object(column))))
+        } else {
+            self.value()
+        }
+    }
+
+    fn key(&mut self) -> ReaderResult<String> {
+        match self.peek() {
+            Some(quote @ '\'') | Some(quote @ '"') => {
+                if self.rest().starts_with("'''") || self.rest().starts_with("\"\"\"") {
+                    return self.err("a block string cannot be a key");
+                }
+                self.bump();
+                self.string(quote)
+            }
+            Some(c) if is_ident_start(c) => Ok(self.ident().to_string()),
+            Some('0'...'9') => {
+                let start = self.pos;
+                match try!(self.number()) {
+                    repr::I64(v) => Ok(v.to_string()),
+                    repr::U64(v) => Ok(v.to_string()),
+                    // Rust and JavaScript agree on the shortest digits, but JavaScript
+                    // switches to an exponent outside of this range
+                    repr::F64(v) if v == 0.0 || (1e-6 <= v && v < 1e21) => Ok(v.to_string()),
+                    _ => self.err_at(start, "a number key which JavaScript writes with \
+                                             an exponent is not supported"),
+                }
+            }
+            _ => self.err("expected a key"),
+        }
+    }
+
+    fn ident(&mut self) -> &'a str {
+        let start = self.pos;
+        self.bump();
+        while self.peek_is(is_ident_continue) { self.bump(); }
+        &self.text[start..self.pos]
+    }
+
+    fn value(&mut self) -> ReaderResult<repr::Atom<'static>> {
+        let value = match self.peek() {
+            Some('{') => repr::Object(try!(self.braced_object())),
+            Some('[') => repr::Array(try!(self.array())),
+            Some(quote @ '\'') | Some(quote @ '"') => {
+                let triple = if quote == '\'' { "'''" } else { "\"\"\"" };
+                if self.rest().starts_with(triple) {
+                    self.pos += 3;
+                    repr::OwnedString(try!(self.block_string(quote)))
+                } else {
+                    self.bump();
+                    repr::OwnedString(try!(self.string(quote)))
+                }
+            }
+            Some(sign @ '-') | Some(sign @ '+') if self.rest()[1..].starts_with("Infinity") => {
+                self.bump();
+                let start = self.pos;
+                if self.ident() != "Infinity" {
+                    self.pos = start;
+                    return self.err("expected `Infinity` after the sign");
+                }
+                repr::F64(if sign == '-' { f64::NEG_INFINITY } else { f64::INFINITY })
+            }
+            Some('0'...'9') | Some('.') | Some('-') | Some('+') => try!(self.number()),
+            Some(c) if is_ident_start(c) => {
+                let start = self.pos;
+                match self.ident() {
+                    "true" | "yes" | "on" => repr::True,
+                    "false" | "no" | "off" => repr::False,
+                    "null" => repr::Null,
+                    "NaN" => repr::F64(f64::NAN),
+                    "Infinity" => repr::F64(f64::INFINITY),
+                    "undefined" => {
+                        self.pos = start;
+                        return self.err("`undefined` has no equivalent in CSON");
+                    }
+                    name => {
+                        self.pos = start;
+                        return self.unsupported(&format!("a reference to `{}`", name));
+                    }
+                }
+            }
+            Some('@') => { return self.unsupported("a reference to `this`"); }
+            Some('(') => { return self.unsupported("a parenthesized expression or function"); }
+            Some('/') => { return self.unsupported("a regular expression"); }
+            Some('`') => { return self.unsupported("embedded JavaScript"); }
+            Some(c) => { return self.err(format!("unexpected character `{}`", c)); }
+            None => { return self.err("expected a value"); }
+        };
+
+        // anything following a value in the same line would make an expression
+        try!(self.skip_inline());
+        match self.peek() {
+            Some('-') if self.peek_nth(1) == Some('>') => self.unsupported("a function"),
+            Some('=') if self.peek_nth(1) == Some('>') => self.unsupported("a function"),
+            Some('(') | Some('.') | Some('?') | Some('[') => self.unsupported("an expression"),
+            Some('+') | Some('-') | Some('*') | Some('/') | Some('%') | Some('&') |
+            Some('|') | Some('^') | Some('<') | Some('>') | Some('=') | Some('!') => {
+                self.unsupported("an operator")
+            }
+            Some(c) if is_ident_start(c) => {
+                self.unsupported("an expression or a function call")
+            }
+            Some('\'') | Some('"') | Some('0'...'9') => self.unsupported("a function call"),
+            _ => Ok(value),
+        }
+    }
+
+    fn braced_object(&mut self) -> ReaderResult<repr::AtomObject<'static>> {
+        self.nested(|p| p.braced_members())
+    }
+
+    fn braced_members(&mut self) -> ReaderResult<repr::AtomObject<'static>> {
+        self.bump();
+        let mut items = repr::AtomObject::new();
+        loop {
+            try!(self.skip_all());
+            if self.eat('}') { break; }
+            let column = self.column();
+            let key = try!(self.key());
+            try!(self.skip_inline());
+            if !self.eat(':') { return self.err("expected `:`"); }
+            let value = try!(self.member_value(column));
+            items.insert(Key::new(key), value);
+            try!(self.skip_inline());
+            if !self.eat(',') && !self.at_eol() && self.peek() != Some('}') {
+                return self.err("expected `,` or `}`");
+            }
+            if self.peek().is_none() { return self.err("expected `}`"); }
+        }
+        Ok(items)
+    }
+
+    fn array(&mut self) -> ReaderResult<repr::AtomArray<'static>> {
+        self.nested(|p| p.array_items())
+    }
+
+    fn array_items(&mut self) -> ReaderResult<repr::AtomArray<'static>> {
+        self.bump();
+        let mut items = Vec::new();
+        loop {
+            try!(self.skip_all());
+            if self.eat(']') { break; }
+            // a lone comma separates implicit objects in different lines
+            if !items.is_empty() && self.eat(',') { continue; }
+            let item = if self.starts_member() {
+                let column = self.column();
+                repr::Object(try!(self.implicit_object(column)))
+            } else {
+                try!(self.value())
+            };
+            items.push(item);
+            try!(self.skip_inline());
+            if !self.eat(',') && !self.at_eol() && self.peek() != Some(']') {
+                return self.err("expected `,` or `]`");
+            }
+            if self.peek().is_none() { return self.err("expected `]`"); }
+        }
+        Ok(items)
+    }
+
+    /// Parses a JavaScript number literal with an optional sign.
+    fn number(&mut self) -> ReaderResult<repr::Atom<'static>> {
+        let start = self.pos;
+        let negative = self.eat('-');
+        if !negative { self.eat('+'); }
+        let radix = match (self.peek(), self.peek_nth(1)) {
+            (Some('0'), Some('x')) | (Some('0'), Some('X')) => 16,
+            (Some('0'), Some('o')) | (Some('0'), Some('O')) => 8,
+            (Some('0'), Some('b')) | (Some('0'), Some('B')) => 2,
+            _ => 10,
+        };
+        if radix != 10 {
+            self.pos += 2;
+            let digits_start = self.pos;
+            while self.peek_is(|c| c.is_digit(radix)) { self.bump(); }
+            let digits = &self.text[digits_start..self.pos];
+            let v = match u64::from_str_radix(digits, radix) {
+                Ok(v) => v,
+                Err(_) if digits.is_empty() => { return self.err("expected digits"); }
+                Err(_) => { return Ok(repr::F64(parse_big(digits, radix, negative))); }
+            };
+            return Ok(integer(v, negative));
+        }
+
+        let digits_start = self.pos;
+        while self.peek_is(|c| c.is_ascii_digit()) { self.bump(); }
+        let int_len = self.pos - digits_start;
+        if int_len > 1 && self.text[digits_start..].starts_with('0') {
+            return self.err_at(digits_start, "legacy octal literals are not supported");
+        }
+        let mut integral = true;
+        if self.peek() == Some('.') && self.rest()[1..].starts_with(|c: char| c.is_ascii_digit()) {
+            self.bump();
+            while self.peek_is(|c| c.is_ascii_digit()) { self.bump(); }
+            integral = false;
+        } else if int_len == 0 {
+            return self.err_at(start, "expected a number");
+        }
+        if let Some('e') = self.peek().map(|c| c.to_ascii_lowercase()) {
+            self.bump();
+            if !self.eat('-') { self.eat('+'); }
+            let exp_start = self.pos;
+            while self.peek_is(|c| c.is_ascii_digit()) { self.bump(); }
+            if self.pos == exp_start { return self.err("a number has an incomplete exponent"); }
+            integral = false;
+        }
+        if self.peek_is(is_ident_start) {
+            return self.err("an identifier cannot follow a number");
+        }
+
+        let text = self.text[start..self.pos].trim_left_matches('+');
+        if integral {
+            if let Ok(v) = text.parse::<i64>() { return Ok(repr::I64(v)); }
+            if let Ok(v) = text.parse::<u64>() { return Ok(repr::U64(v)); }
+        }
+        // Rust does not accept `.5`
+        let text = text.replacen("-.", "-0.", 1);
+        let text = if text.starts_with('.') { format!("0{}", text) } else { text };
+        Ok(repr::F64(text.parse().unwrap()))
+    }
+
+    /// Parses the rest of a quoted string after the opening quote.
+    ///
+    /// Newlines in the string are folded into a single space along with
+    /// the surrounding whitespace, as CoffeeScript does.
+    fn string(&mut self, quote: char) -> ReaderResult<String> {
+        let start = self.pos - 1;
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                None => { return self.err_at(start, "unterminated string"); }
+                Some(c) if c == quote => { return Ok(s); }
+                Some('\\') => { try!(self.escape(&mut s)); }
+                Some('#') if quote == '"' && self.peek() == Some('{') => {
+                    self.pos -= 1;
+                    return self.unsupported("string interpolation");
+                }
+                Some(c @ '\n') | Some(c @ '\r') => {
+                    if c == '\r' { self.eat('\n'); }
+                    let trimmed = s.trim_right_matches(|c| c == ' ' || c == '\t').len();
+                    s.truncate(trimmed);
+                    while self.peek() == Some(' ') || self.peek() == Some('\t') { self.bump(); }
+                    if !s.is_empty() && self.peek() != Some(quote) { s.push(' '); }
+                }
+                Some(c) => { s.push(c); }
+            }
+        }
+    }
+
+    /// Parses the rest of a block string after the opening `'''` or `"""`.
+    ///
+    /// The first and last lines are removed if they are blank,
+    /// and the common indentation of the other lines is removed.
+    fn block_string(&mut self, quote: char) -> ReaderResult<String> {
+        let start = self.pos - 3;
+        let triple: String = (0..3).map(|_| quote).collect();
+        let mut raw = String::new();
+        loop {
+            if self.rest().starts_with(&triple[..]) { self.pos += 3; break; }
+            match self.bump() {
+                None => { return self.err_at(start, "unterminated block string"); }
+                Some('\\') => {
+                    // keep escapes as is until the indentation is removed
+                    raw.push('\\');
+                    if let Some(c) = self.bump() { raw.push(c); }
+                }
+                Some('#') if quote == '"' && self.peek() == Some('{') => {
+                    self.pos -= 1;
+                    return self.unsupported("string interpolation");
+                }
+                Some('\r') => { self.eat('\n'); raw.push('\n'); }
+                Some(c) => { raw.push(c); }
+            }
+        }
+
+        let mut lines: Vec<&str> = raw.split('\n').collect();
+        if lines.len() > 1 && lines[0].trim().is_empty() { lines.remove(0); }
+        if lines.len() > 1 && lines.last().unwrap().trim().is_empty() { lines.pop(); }
+        // spaces and tabs are single bytes, so the indentation can be sliced in bytes
+        let is_indent = |b: &u8| *b == b' ' || *b == b'\t';
+        let indent = lines.iter().filter(|l| !l.trim().is_empty()).map(|l| {
+            l.bytes().take_while(&is_indent).count()
+        }).min().unwrap_or(0);
+        let dedented: Vec<&str> = lines.iter().map(|l| {
+            &l[l.bytes().take(indent).take_while(&is_indent).count()..]
+        }).collect();
+        let joined = dedented.join("\n");
+
+        let mut p = Parser { text: &joined, pos: 0, depth: 0, max_depth: 0 };
+        let mut s = String::new();
+        while let Some(c) = p.bump() {
+            if c == '\\' {
+                if let Err(mut e) = p.escape(&mut s) {
+                    // report the error at the start of the string
+                    e.pos = Some(Position::from_offset(self.text.as_bytes(), start));
+                    return Err(e);
+                }
+            } else {
+                s.push(c);
+            }
+        }
+        Ok(s)
+    }
+
+    /// Parses an escape sequence after `\` as JavaScript does.
+    fn escape(&mut self, s: &mut String) -> ReaderResult<()> {
+        let c = match self.bump() {
+            Some(c) => c,
+            None => { return self.err("incomplete escape sequence"); }
+        };
+        match c {
+            'n' => s.push('\n'),
+            'r' => s.push('\r'),
+            't' => s.push('\t'),
+            'b' => s.push('\x08'),
+            'f' => s.push('\x0c'),
+            'v' => s.push('\x0b'),
+            '0' if !self.peek_is(|c| c.is_ascii_digit()) => s.push('\0'),
+            '1'...'9' | '0' => { return self.err("octal escape sequences are not supported"); }
+            'x' => {
+                let v = try!(self.hex_digits(2));
+                s.push(::std::char::from_u32(v).unwrap());
+            }
+            'u' if self.eat('{') => {
+                let start = self.pos;
+                while self.peek_is(|c| c.is_ascii_hexdigit()) { self.bump(); }
+                let digits = &self.text[start..self.pos];
+                if digits.is_empty() || !self.eat('}') {
+                    return self.err("invalid `\\u{...}` escape sequence");
+                }
+                match u32::from_str_radix(digits, 16).ok().and_then(::std::char::from_u32) {
+                    Some(c) => s.push(c),
+                    None => { return self.err("`\\u{...}` is not a valid character"); }
+                }
+            }
+            'u' => {
+                let first = try!(self.hex_digits(4));
+                let c = if (0xd800..0xdc00).contains(&first) && self.rest().starts_with("\\u") {
+                    self.pos += 2;
+                    let second = try!(self.hex_digits(4));
+                    if !(0xdc00..0xe000).contains(&second) {
+                        return self.err("invalid surrogate pair");
+                    }
+                    0x10000 + ((first - 0xd800) << 10) + (second - 0xdc00)
+                } else {
+                    first
+                };
+                match ::std::char::from_u32(c) {
+                    Some(c) => s.push(c),
+                    None => { return self.err("unpaired surrogate in a `\\u` escape sequence"); }
+                }
+            }
+            '\r' | '\n' => {
+                // a line continuation
+                if c == '\r' { self.eat('\n'); }
+                while self.peek() == Some(' ') || self.peek() == Some('\t') { self.bump(); }
+            }
+            // any other character escapes itself
+            c => s.push(c),
+        }
+        Ok(())
+    }
+
+    fn hex_digits(&mut self, n: usize) -> ReaderResult<u32> {
+        let mut v = 0;
+        for _ in 0..n {
+            match self.peek().and_then(|c| c.to_digit(16)) {
+                Some(d) => { v = v * 16 + d; self.bump(); }
+                None => { return self.err("invalid hexadecimal digits in an escape sequence"); }
+            }
+        }
+        Ok(v)
+    }
+}
+
+fn integer(v: u64, negative: bool) -> repr::Atom<'static> {
+    if !negative {
+        if v <= i64::MAX as u64 { repr::I64(v as i64) } else { repr::U64(v) }
+    } else if v <= 1 << 63 {
+        repr::I64((v as i64).wrapping_neg())
+    } else {
+        repr::F64(-(v as f64))
+    }
+}
+
+/// Approximates an integer too large for `u64`, as JavaScript does.
+fn parse_big(digits: &str, radix: u32, negative: bool) -> f64 {
+    let v = digits.chars().fold(0.0, |v, c| v * radix as f64 + c.to_digit(radix).unwrap() as f64);
+    if negative { -v } else { v }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_document_from_str as parse;
+    use super::Parser;
+    use repr;
+    use repr::{Null, True, False, I64, U64, F64};
+
+    #[allow(non_snake_case)] // make it look like a constructor
+    fn String(s: &str) -> repr::Atom<'static> { repr::OwnedString(s.to_string()) }
+    macro_rules! array { [$($e:expr),*] => (repr::Array(vec![$($e),*])) }
+    macro_rules! object { [$($k:expr => $v:expr),*] =>
+        (repr::Object(vec![$((repr::Key::new($k), $v)),*].into_iter().collect())) }
+
+    fn error_at(text: &str) -> (usize, usize) {
+        let pos = parse(text).unwrap_err().pos.unwrap();
+        (pos.line, pos.column)
+    }
+
+    #[test]
+    fn test_coffee() {
+        let text = "\
+# package manifest
+name: 'cson'
+'version': \"1.0\"
+private: yes
+engines:
+  atom: '>=1.0'
+
+  ###
+  block comment
+  ###
+  node: off
+nested: deep: 0x1f
+list: [
+  1, -2.5
+  .5
+  name: 'a'
+  bad: null
+,
+  name: 'b'
+]
+inline: {a: 1, 'b': [true, false]}
+text: '''
+    first
+      second\\n
+  '''
+folded: 'one
+         two'
+big: 18446744073709551615
+";
+        assert_eq!(parse(text).unwrap(), object! {
+            "name" => String("cson"),
+            "version" => String("1.0"),
+            "private" => True,
+            "engines" => object! { "atom" => String(">=1.0"), "node" => False },
+            "nested" => object! { "deep" => I64(31) },
+            "list" => array![I64(1), F64(-2.5), F64(0.5),
+                             object! { "name" => String("a"), "bad" => Null },
+                             object! { "name" => String("b") }],
+            "inline" => object! { "a" => I64(1), "b" => array![True, False] },
+            "text" => String("first\n  second\n"),
+            "folded" => String("one two"),
+            "big" => U64(18446744073709551615)
+        });
+        assert_eq!(parse("[1, [2]]").unwrap(), array![I64(1), array![I64(2)]]);
+        assert_eq!(parse("[-Infinity, +Infinity, Infinity]").unwrap(),
+                   array![F64(-1.0 / 0.0), F64(1.0 / 0.0), F64(1.0 / 0.0)]);
+        // number keys are normalized as in JavaScript
+        assert_eq!(parse("0x10: 1\n1e3: 2\n1.50: 3\n0b11: 4").unwrap(),
+                   object! { "16" => I64(1), "1000" => I64(2), "1.5" => I64(3), "3" => I64(4) });
+        assert_eq!(parse("\"\"\"\n  a\n  b\n\"\"\"").unwrap(), String("a\nb"));
+        // only ASCII spaces and tabs are removed as the indentation
+        assert_eq!(parse("a: '''\n  x\n\u{3000}\n  '''").unwrap(),
+                   object! { "a" => String("x\n\u{3000}") });
+        assert_eq!(parse("a: '''\n    x\n  \n    y\n'''").unwrap(),
+                   object! { "a" => String("x\n\ny") });
+
+        // executable constructs are rejected where they start
+        assert_eq!(error_at("a: b"), (1, 4));
+        assert_eq!(error_at("a: \"x#{y}\""), (1, 6));
+        assert_eq!(error_at("a: 1 + 2"), (1, 6));
+        assert_eq!(error_at("a:\n  f: (x) -> x"), (2, 6));
+        assert_eq!(error_at("a: /x/"), (1, 4));
+        assert_eq!(error_at("a: @b"), (1, 4));
+        assert!(parse("a: b")
+                    .unwrap_err().cause.contains("a reference to `b` is not supported"));
+
+        // the indentation should be consistent
+        assert_eq!(error_at("a:\n  b: 1\n    c: 2"), (3, 5));
+        assert_eq!(error_at("a: '''x"), (1, 4));
+        assert_eq!(error_at("a: 017"), (1, 4));
+        assert_eq!(error_at("a: -Infinityx"), (1, 5));
+        assert_eq!(error_at("a: 1\n1e-7: 2"), (2, 1));
+        assert_eq!(error_at("a: 1\n1e21: 2"), (2, 1));
+
+        // the depth is balanced after errors
+        let mut parser = Parser { text: "[{a: [1 2]}]", pos: 0, depth: 0, max_depth: 128 };
+        assert!(parser.value().is_err());
+        assert_eq!(parser.depth, 0);
+    }
+}
@@ -0,0 +1,1390 @@
+// This is a part of CSON-rust.
+// Written by Kang Seonghoon. See README.md for details.
+
+//! A lossless concrete syntax tree of CSON.
+//!
+//! `reader::Reader` discards whitespace, comments and the choice of separators and quotes.
+//! `Document` instead keeps every token of the input in a tree,
+//! so that printing an unchanged document reproduces the input byte for byte.
+//! The tree can be converted to `repr::Atom` at any time.
+
+use std::{fmt, io, mem};
+use super::repr;
+use super::reader::{Reader, ReaderResult, ReaderError, ErrorKind, Limit, Options,
+                     Position};
+use super::lexer::Lexer;
+use super::writer;
+use super::writer::Writer;
+
+pub use super::lexer::{Span, Quote, Token, TokenKind};
+pub use super::repr::PathItem;
+
+/// A kind of nodes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NodeKind {
+    /// The whole input, consisting of the leading trivia, the top-level object or array,
+    /// and the trailing trivia.
+    Document,
+    /// An object. The top-level object of a braceless document has no braces.
+    Object,
+    /// An array.
+    Array,
+    /// `name name-separator value` in an object.
+    Member,
+    /// One or more lines of a verbatim string, with newlines and trivia between them.
+    VerbatimString,
+}
+
+/// A node of the syntax tree.
+///
+/// The children include every token covered by the node in the input order,
+/// including trivia (whitespace, newlines and comments) and separators.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub span: Span,
+    pub children: Vec<Element>,
+}
+
+/// A child of a node, which is either a nested node or a token.
+///
+/// Scalar values (literals, numbers and quoted strings) are represented as tokens.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Element {
+    Node(Node),
+    Token(Token),
+}
+
+impl Element {
+    pub fn span(&self) -> Span {
+        match *self {
+            Element::Node(ref node) => node.span,
+            Element::Token(ref token) => token.span,
+        }
+    }
+
+    /// Returns true if the element is a whitespace, a newline or a comment.
+    pub fn is_trivia(&self) -> bool {
+        match *self {
+            Element::Node(_) => false,
+            Element::Token(ref token) => token.kind.is_trivia(),
+        }
+    }
+
+    fn push_tokens(&self, tokens: &mut Vec<Token>) {
+        match *self {
+            Element::Node(ref node) => {
+                for child in &node.children { child.push_tokens(tokens); }
+            }
+            Element::Token(token) => { tokens.push(token); }
+        }
+    }
+}
+
+impl Node {
+    fn new(kind: NodeKind, children: Vec<Element>, offset: usize) -> Node {
+        let span = match (children.first(), children.last()) {
+            (Some(first), Some(last)) => Span::new(first.span().start, last.span().end),
+            _ => Span::new(offset, offset),
+        };
+        Node { kind: kind, span: span, children: children }
+    }
+
+    /// Returns every token covered by the node in the input order.
+    pub fn tokens(&self) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        for child in &self.children { child.push_tokens(&mut tokens); }
+        tokens
+    }
+
+    /// Returns the children other than trivia.
+    pub fn significant_children(&self) -> Vec<&Element> {
+        self.children.iter().filter(|e| !e.is_trivia()).collect()
+    }
+
+    /// Returns true if the node is an object or array enclosed in brackets.
+    pub fn is_braced(&self) -> bool {
+        match self.children.first() {
+            Some(&Element::Token(Token { kind: TokenKind::BeginObject, .. })) |
+            Some(&Element::Token(Token { kind: TokenKind::BeginArray, .. })) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the top-level object or array of a document.
+    pub fn root_value(&self) -> Option<&Node> {
+        if self.kind != NodeKind::Document { return None; }
+        self.children.iter().filter_map(|e| match *e {
+            Element::Node(ref node) => Some(node),
+            Element::Token(_) => None,
+        }).next()
+    }
+
+    /// Returns the members of an object in the input order.
+    pub fn members(&self) -> Vec<&Node> {
+        if self.kind != NodeKind::Object { return Vec::new(); }
+        self.children.iter().filter_map(|e| match *e {
+            Element::Node(ref node) if node.kind == NodeKind::Member => Some(node),
+            _ => None,
+        }).collect()
+    }
+
+    /// Returns the elements of an array in the input order.
+    pub fn elements(&self) -> Vec<&Element> {
+        if self.kind != NodeKind::Array { return Vec::new(); }
+        self.children.iter().filter(|e| match **e {
+            Element::Node(_) => true,
+            Element::Token(ref token) => is_value_token(token.kind),
+        }).collect()
+    }
+
+    /// Returns the name token of a member.
+    pub fn key(&self) -> Option<Token> {
+        if self.kind != NodeKind::Member { return None; }
+        match self.children.first() {
+            Some(&Element::Token(token)) => Some(token),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of a member.
+    pub fn value(&self) -> Option<&Element> {
+        if self.kind != NodeKind::Member { return None; }
+        self.children.last()
+    }
+}
+
+fn is_value_token(kind: TokenKind) -> bool {
+    match kind {
+        TokenKind::Literal | TokenKind::Number | TokenKind::QuotedString(_) => true,
+        _ => false,
+    }
+}
+
+/// A lossless syntax tree of a CSON document along with its source text.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Document {
+    text: String,
+    root: Node,
+    /// Every token in the text, which is used to look up the layout around edits.
+    tokens: Vec<Token>,
+    options: Options,
+}
+
+impl Document {
+    /// Parses a document. Any input accepted by `Reader::parse_document` with
+    /// the default options is accepted.
+    pub fn parse(text: &str) -> ReaderResult<Document> {
+        Document::parse_with_options(text, Options::default())
+    }
+
+    /// Parses a document with given reader options, which are also used for every
+    /// conversion to `repr` types and for reparsing after edits.
+    ///
+    /// The input should be accepted by `Reader::parse_document` with the options,
+    /// so that for example `Options::strict_json` and `DuplicateKeys::Error` are enforced.
+    /// Radix prefixes and digit separators of `Options::extended_numbers` are kept as written.
+    /// Other extensions changing the syntax (e.g. `Options::json5` or `Options::bare_values`)
+    /// are not understood by the syntax tree and such input is still rejected.
+    pub fn parse_with_options(text: &str, options: Options) -> ReaderResult<Document> {
+        // the reader checks everything the lexer does not, such as escape sequences and
+        // numeric ranges, so the syntax tree parser only has to get the structure
+        try!(Reader::with_options(&mut text.as_bytes(), options.clone()).parse_document());
+        let (root, tokens) = {
+            let mut parser = Parser {
+                text: text,
+                tokens: Lexer::new(text).collect(),
+                next: 0,
+                depth: 0,
+                options: &options,
+            };
+            let root = try!(parser.document());
+            (root, parser.tokens)
+        };
+        Ok(Document { text: text.to_string(), root: root, tokens: tokens, options: options })
+    }
+
+    /// Returns the reader options used for the document.
+    pub fn options(&self) -> &Options { &self.options }
+
+    /// Returns the source text.
+    pub fn text(&self) -> &str { &self.text }
+
+    /// Returns the root node, whose kind is `NodeKind::Document`.
+    pub fn root(&self) -> &Node { &self.root }
+
+    /// Returns the source text covered by given span.
+    pub fn slice(&self, span: Span) -> &str { &self.text[span.start..span.end] }
+
+    /// Converts the whole document to `repr::Atom`.
+    pub fn to_atom(&self) -> ReaderResult<repr::Atom<'static>> {
+        self.parse_document_text(&self.text)
+    }
+
+    /// Converts an element of the document to `repr::Atom`.
+    /// A member is converted to its value.
+    pub fn element_to_atom(&self, element: &Element) -> ReaderResult<repr::Atom<'static>> {
+        match *element {
+            Element::Node(ref node) => self.node_to_atom(node),
+            Element::Token(token) => self.parse_value_text(self.slice(token.span)),
+        }
+    }
+
+    /// Converts a node of the document to `repr::Atom`. A member is converted to its value.
+    pub fn node_to_atom(&self, node: &Node) -> ReaderResult<repr::Atom<'static>> {
+        match node.kind {
+            NodeKind::Document => self.to_atom(),
+            NodeKind::Member => self.element_to_atom(node.value().unwrap()),
+            NodeKind::Object if !node.is_braced() => {
+                self.parse_document_text(self.slice(node.span))
+            }
+            _ => self.parse_value_text(self.slice(node.span)),
+        }
+    }
+
+    fn parse_document_text(&self, text: &str) -> ReaderResult<repr::Atom<'static>> {
+        Reader::with_options(&mut text.as_bytes(), self.options.clone()).parse_document()
+    }
+
+    fn parse_value_text(&self, text: &str) -> ReaderResult<repr::Atom<'static>> {
+        Reader::with_options(&mut text.as_bytes(), self.options.clone()).parse_value()
+    }
+
+    /// Converts the whole document to `repr::Spanned`, which keeps the position of
+    /// every value, key and container.
+    pub fn to_spanned(&self) -> ReaderResult<repr::Spanned<'static>> {
+        let lines = LineMap::new(&self.text);
+        self.spanned_node(self.root.root_value().unwrap(), &lines)
+    }
+
+    fn spanned(&self, element: &Element,
+               lines: &LineMap) -> ReaderResult<repr::Spanned<'static>> {
+        match *element {
+            Element::Node(ref node) => self.spanned_node(node, lines),
+            Element::Token(token) => {
+                let value = repr::SpannedValue::Scalar(try!(self.element_to_atom(element)));
+                Ok(repr::Spanned { start: lines.position(token.span.start),
+                                   end: lines.position(token.span.end), value: value })
+            }
+        }
+    }
+
+    fn spanned_node(&self, node: &Node,
+                    lines: &LineMap) -> ReaderResult<repr::Spanned<'static>> {
+        let value = match node.kind {
+            NodeKind::Object => {
+                let mut items = Vec::new();
+                for member in node.members() {
+                    let key = member.key().unwrap();
+                    let key = repr::SpannedKey {
+                        start: lines.position(key.span.start),
+                        end: lines.position(key.span.end),
+                        key: repr::Key::new(self.key_of(member).unwrap()),
+                    };
+                    items.push((key, try!(self.spanned(member.value().unwrap(), lines))));
+                }
+                repr::SpannedValue::Object(items)
+            }
+            NodeKind::Array => {
+                let mut items = Vec::new();
+                for element in node.elements() {
+                    items.push(try!(self.spanned(element, lines)));
+                }
+                repr::SpannedValue::Array(items)
+            }
+            _ => repr::SpannedValue::Scalar(try!(self.node_to_atom(node))),
+        };
+        Ok(repr::Spanned { start: lines.position(node.span.start),
+                           end: lines.position(node.span.end), value: value })
+    }
+
+    /// Converts the whole document to `repr::Annotated`, which keeps comments
+    /// around each member and element.
+    ///
+    /// Comments directly before the top-level object or array (or before the first member
+    /// of a braceless document) and after it in the same line are kept as well.
+    pub fn to_annotated(&self) -> ReaderResult<repr::Annotated<'static>> {
+        let children = &self.root.children;
+        let index = children.iter().position(|e| !e.is_trivia()).unwrap();
+        let root = match children[index] {
+            Element::Node(ref node) => node,
+            Element::Token(_) => unreachable!(),
+        };
+        if root.is_braced() {
+            let mut items = try!(self.annotate_items(children.iter().collect()));
+            Ok(items.pop().unwrap().1)
+        } else {
+            // the trivia around the braceless object belongs to the document node
+            let context = children[..index].iter().chain(root.children.iter())
+                                           .chain(children[index + 1..].iter()).collect();
+            let items = try!(self.annotate_items(context));
+            let items = items.into_iter().map(|(k, v)| (repr::Key::new(k.unwrap()), v));
+            Ok(repr::Annotated::new(repr::AnnotatedValue::Object(items.collect())))
+        }
+    }
+
+    fn annotate(&self, element: &Element) -> ReaderResult<repr::Annotated<'static>> {
+        let value = match *element {
+            Element::Node(ref node) if node.kind == NodeKind::Object => {
+                let items = try!(self.annotate_items(node.children.iter().collect()));
+                repr::AnnotatedValue::Object(items.into_iter().map(|(k, v)| {
+                    (repr::Key::new(k.unwrap()), v)
+                }).collect())
+            }
+            Element::Node(ref node) if node.kind == NodeKind::Array => {
+                let items = try!(self.annotate_items(node.children.iter().collect()));
+                repr::AnnotatedValue::Array(items.into_iter().map(|(_, v)| v).collect())
+            }
+            _ => repr::AnnotatedValue::Scalar(try!(self.element_to_atom(element))),
+        };
+        Ok(repr::Annotated::new(value))
+    }
+
+    /// Annotates members or elements in `children` with the comments between them.
+    /// Returns a list of keys (for members) and values.
+    fn annotate_items(&self, children: Vec<&Element>)
+            -> ReaderResult<Vec<(Option<String>, repr::Annotated<'static>)>> {
+        let mut items: Vec<(Option<String>, repr::Annotated<'static>)> = Vec::new();
+        let mut pending = Vec::new();
+        let mut newlines = 0;
+        let mut after_item = false;
+        for element in children {
+            let (key, value) = match *element {
+                Element::Token(token) if token.kind == TokenKind::Newline => {
+                    newlines += 1;
+                    continue;
+                }
+                Element::Token(token) if token.kind == TokenKind::Comment => {
+                    let text = self.slice(token.span)[1..].to_string();
+                    if after_item && newlines == 0 {
+                        items.last_mut().unwrap().1.comment_after = Some(text);
+                    } else {
+                        // a blank line separates comments from what follows
+                        if newlines >= 2 { pending.clear(); }
+                        pending.push(text);
+                    }
+                    after_item = false;
+                    newlines = 0;
+                    continue;
+                }
+                Element::Token(token) if !is_value_token(token.kind) => { continue; }
+                Element::Node(ref node) if node.kind == NodeKind::Member => {
+                    (self.key_of(node), try!(self.annotate(node.value().unwrap())))
+                }
+                _ => (None, try!(self.annotate(element))),
+            };
+            let mut value = value;
+            if newlines >= 2 { pending.clear(); }
+            value.comments_before = mem::replace(&mut pending, Vec::new());
+            items.push((key, value));
+            after_item = true;
+            newlines = 0;
+        }
+        Ok(items)
+    }
+}
+
+/// An error from editing a document.
+#[derive(Debug)]
+pub enum EditError {
+    /// The path does not lead to a value, or its parent is not an object or array.
+    NotFound,
+    /// The key to insert or rename to already exists.
+    DuplicateKey,
+    /// The value cannot be written in CSON.
+    Unwritable(io::Error),
+    /// The edited document cannot be parsed. This would be a bug.
+    Reparse(ReaderError),
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EditError::NotFound => write!(f, "no value at given path"),
+            EditError::DuplicateKey => write!(f, "the key already exists"),
+            EditError::Unwritable(ref err) => write!(f, "cannot write the value ({})", *err),
+            EditError::Reparse(ref err) => {
+                write!(f, "cannot parse the edited document ({})", *err)
+            }
+        }
+    }
+}
+
+pub type EditResult<T> = Result<T, EditError>;
+
+/// A replacement of the text in `Span`.
+type Splice = (Span, String);
+
+/// Returns the index of every member or element among the children of an object or array.
+fn item_indices(container: &Node) -> Vec<usize> {
+    container.children.iter().enumerate().filter(|&(_, e)| match *e {
+        Element::Node(_) => true,
+        Element::Token(ref token) => is_value_token(token.kind),
+    }).map(|(i, _)| i).collect()
+}
+
+/// Editing operations. Each operation only rewrites the affected region of the text,
+/// formats new values after the surrounding style, and parses the edited text again.
+impl Document {
+    /// Returns the value at `path`. An empty path refers to the top-level object or array.
+    ///
+    /// If there are duplicate keys, the last one is used as `Reader` does by default.
+    pub fn get(&self, path: &[PathItem]) -> Option<&Element> {
+        let (last, init) = match path.split_last() {
+            Some(split) => split,
+            None => { return self.root.children.iter().find(|e| !e.is_trivia()); }
+        };
+        let container = match self.container(init) {
+            Some(container) => container,
+            None => { return None; }
+        };
+        self.child(container, *last).map(|i| item_value(&container.children[i]))
+    }
+
+    /// Replaces the value at `path`, or inserts a new member if the last key is missing.
+    pub fn set(&mut self, path: &[PathItem], value: &repr::Atom) -> EditResult<()> {
+        let splices = {
+            let (last, init) = match path.split_last() {
+                Some(split) => split,
+                None => {
+                    let root = self.root.root_value().unwrap();
+                    let text = match *value {
+                        // keep a braceless document braceless
+                        repr::Object(ref items) if !root.is_braced() && !items.is_empty() => {
+                            try!(self.format_braceless(value, root))
+                        }
+                        _ => try!(self.format_value(value, root, "")),
+                    };
+                    return self.apply(vec![(root.span, text)]);
+                }
+            };
+            let container = try!(self.container(init).ok_or(EditError::NotFound));
+            match self.child(container, *last) {
+                Some(i) => {
+                    let span = item_value(&container.children[i]).span();
+                    let indent = self.indent_at(span.start).to_string();
+                    vec![(span, try!(self.format_value(value, container, &indent)))]
+                }
+                None => try!(self.insert_splices(container, *last, value)),
+            }
+        };
+        self.apply(splices)
+    }
+
+    /// Inserts a new member into an object or a new element into an array.
+    ///
+    /// For an array, the element is inserted before the existing element at the index,
+    /// or appended if the index equals the number of elements.
+    pub fn insert(&mut self, path: &[PathItem], value: &repr::Atom) -> EditResult<()> {
+        let splices = {
+            let (last, init) = try!(path.split_last().ok_or(EditError::NotFound));
+            let container = try!(self.container(init).ok_or(EditError::NotFound));
+            if let PathItem::Key(_) = *last {
+                if self.child(container, *last).is_some() {
+                    return Err(EditError::DuplicateKey);
+                }
+            }
+            try!(self.insert_splices(container, *last, value))
+        };
+        self.apply(splices)
+    }
+
+    /// Removes a member or element. A member or element in its own line is removed
+    /// along with the whole line, including the trailing comment.
+    pub fn remove(&mut self, path: &[PathItem]) -> EditResult<()> {
+        let splices = {
+            let (last, init) = try!(path.split_last().ok_or(EditError::NotFound));
+            let container = try!(self.container(init).ok_or(EditError::NotFound));
+            let index = try!(self.child(container, *last).ok_or(EditError::NotFound));
+            self.remove_splices(container, index)
+        };
+        self.apply(splices)
+    }
+
+    /// Renames the key of a member. The new key is quoted only when the old key is quoted
+    /// or the new key cannot be a bare string.
+    pub fn rename(&mut self, path: &[PathItem], new_key: &str) -> EditResult<()> {
+        let splices = {
+            let (last, init) = try!(path.split_last().ok_or(EditError::NotFound));
+            let container = try!(self.container(init).ok_or(EditError::NotFound));
+            let index = try!(self.child(container, *last).ok_or(EditError::NotFound));
+            match self.child(container, PathItem::Key(new_key)) {
+                Some(i) if i == index => { return Ok(()); } // renamed to itself
+                Some(_) => { return Err(EditError::DuplicateKey); }
+                None => {}
+            }
+            let key = match container.children[index] {
+                Element::Node(ref member) if member.kind == NodeKind::Member => {
+                    member.key().unwrap()
+                }
+                _ => { return Err(EditError::NotFound); }
+            };
+            let mut options = self.writer_options(container);
+            if let TokenKind::QuotedString(quote) = key.kind {
+                options.bare_keys = false;
+                options.quote = quote;
+            } else {
+                options.bare_keys = true;
+            }
+            let mut buf = Vec::new();
+            try!(Writer::with_options(&mut buf, options).write_key(new_key)
+                                                        .map_err(EditError::Unwritable));
+            vec![(key.span, String::from_utf8(buf).unwrap())]
+        };
+        self.apply(splices)
+    }
+
+    /// Returns the decoded key of a member.
+    pub fn key_of(&self, member: &Node) -> Option<String> {
+        let token = match member.key() {
+            Some(token) => token,
+            None => { return None; }
+        };
+        let text = self.slice(token.span);
+        match token.kind {
+            TokenKind::QuotedString(_) => match self.parse_value_text(text) {
+                Ok(repr::OwnedString(s)) => Some(s),
+                _ => None,
+            },
+            _ => Some(text.to_string()),
+        }
+    }
+
+    /// Returns the object or array at `path`.
+    fn container(&self, path: &[PathItem]) -> Option<&Node> {
+        let mut container = match self.root.root_value() {
+            Some(node) => node,
+            None => { return None; }
+        };
+        for &step in path {
+            let index = match self.child(container, step) {
+                Some(index) => index,
+                None => { return None; }
+            };
+            container = match *item_value(&container.children[index]) {
+                Element::Node(ref node) if node.kind == NodeKind::Object ||
+                                           node.kind == NodeKind::Array => node,
+                _ => { return None; }
+            };
+        }
+        Some(container)
+    }
+
+    /// Returns the index of the member or element in the children of `container`.
+    fn child(&self, container: &Node, step: PathItem) -> Option<usize> {
+        let items = item_indices(container);
+        match (container.kind, step) {
+            (NodeKind::Object, PathItem::Key(key)) => items.into_iter().rev().find(|&i| {
+                match container.children[i] {
+                    Element::Node(ref member) => {
+                        self.key_of(member).map_or(false, |k| k == key)
+                    }
+                    Element::Token(_) => false,
+                }
+            }),
+            (NodeKind::Array, PathItem::Index(index)) => items.get(index).cloned(),
+            _ => None,
+        }
+    }
+
+    fn line_start(&self, offset: usize) -> usize {
+        self.text[..offset].rfind(|c| c == '\n' || c == '\r').map_or(0, |i| i + 1)
+    }
+
+    /// Returns the indentation of the line containing `offset`.
+    fn indent_at(&self, offset: usize) -> &str {
+        let line = &self.text[self.line_start(offset)..];
+        let len = line.len() - line.trim_left_matches(|c| c == ' ' || c == '\t').len();
+        &line[..len]
+    }
+
+    /// Returns true if only whitespace precedes `offset` in its line.
+    fn starts_line(&self, offset: usize) -> bool {
+        self.text[self.line_start(offset)..offset].chars().all(|c| c == ' ' || c == '\t')
+    }
+
+    /// Returns the index of the first token starting at or after `offset`.
+    fn token_index(&self, offset: usize) -> usize {
+        match self.tokens.binary_search_by(|t| t.span.start.cmp(&offset)) {
+            Ok(i) | Err(i) => i,
+        }
+    }
+
+    /// Returns the first token starting at or after `offset`.
+    fn token_after(&self, offset: usize) -> Option<Token> {
+        self.tokens.get(self.token_index(offset)).cloned()
+    }
+
+    /// Scans the rest of the line after an item ending at `offset`.
+    ///
+    /// Returns the trailing comma if any, and the end of the item's line excluding
+    /// the trailing whitespace and the newline. The line ends early at any other token.
+    fn line_rest(&self, offset: usize) -> (Option<Span>, usize) {
+        let mut comma = None;
+        let mut end = offset;
+        for token in &self.tokens[self.token_index(offset)..] {
+            match token.kind {
+                TokenKind::Whitespace => {}
+                TokenKind::Comma if comma.is_none() => {
+                    comma = Some(token.span);
+                    end = token.span.end;
+                }
+                TokenKind::Comment => { end = token.span.end; break; }
+                _ => { break; }
+            }
+        }
+        (comma, end)
+    }
+
+    /// Returns one level of indentation used in the document, defaulting to two spaces.
+    fn indent_unit(&self) -> String {
+        for (i, token) in self.tokens.iter().enumerate() {
+            // whitespace right after a newline is an indentation
+            if token.kind == TokenKind::Whitespace && i > 0 &&
+               self.tokens[i - 1].kind == TokenKind::Newline {
+                if let Some(next) = self.tokens.get(i + 1) {
+                    if !next.kind.is_trivia() { return self.slice(token.span).to_string(); }
+                }
+            }
+        }
+        "  ".to_string()
+    }
+
+    /// Returns the writer options following the style of `container` and the document.
+    fn writer_options(&self, container: &Node) -> writer::Options {
+        let mut options = writer::Options::default();
+        options.indent = self.indent_unit();
+        if let Some(token) = self.tokens.iter().find(|t| t.kind == TokenKind::Newline) {
+            options.newline = self.slice(token.span).to_string();
+        }
+        if let Some(quote) = self.tokens.iter().filter_map(|t| match t.kind {
+            TokenKind::QuotedString(quote) => Some(quote),
+            _ => None,
+        }).next() {
+            options.quote = quote;
+        }
+        let has_comma = container.children.iter().any(|e| match *e {
+            Element::Token(ref token) => token.kind == TokenKind::Comma,
+            Element::Node(_) => false,
+        });
+        if has_comma { options.separator = writer::Separator::Comma; }
+        let members = container.members();
+        if !members.is_empty() {
+            options.bare_keys = members.iter().any(|m| match m.key() {
+                Some(key) => key.kind != TokenKind::QuotedString(Quote::Double) &&
+                             key.kind != TokenKind::QuotedString(Quote::Single),
+                None => false,
+            });
+        }
+        // verbatim strings may swallow what follows them in the same line
+        options.verbatim_strings = false;
+        options
+    }
+
+    fn format_value(&self, value: &repr::Atom, container: &Node,
+                    indent: &str) -> EditResult<String> {
+        let mut buf = Vec::new();
+        try!(Writer::with_options(&mut buf, self.writer_options(container))
+                    .write_value(value, indent).map_err(EditError::Unwritable));
+        Ok(String::from_utf8(buf).unwrap())
+    }
+
+    /// Formats an object as the members of a braceless document, without the final newline.
+    fn format_braceless(&self, value: &repr::Atom, container: &Node) -> EditResult<String> {
+        let mut options = self.writer_options(container);
+        options.braceless = true;
+        let newline = options.newline.clone();
+        let mut buf = Vec::new();
+        try!(Writer::with_options(&mut buf, options).write_document(value)
+                                                    .map_err(EditError::Unwritable));
+        let mut text = String::from_utf8(buf).unwrap();
+        if text.ends_with(&newline[..]) {
+            let len = text.len() - newline.len();
+            text.truncate(len);
+        }
+        Ok(text)
+    }
+
+    /// Formats a new member or element, copying the name separator from other members.
+    fn format_item(&self, container: &Node, step: PathItem, value: &repr::Atom,
+                   indent: &str) -> EditResult<String> {
+        let key = match step {
+            PathItem::Key(key) => key,
+            PathItem::Index(_) => { return self.format_value(value, container, indent); }
+        };
+        let mut buf = Vec::new();
+        try!(Writer::with_options(&mut buf, self.writer_options(container))
+                    .write_key(key).map_err(EditError::Unwritable));
+        let mut text = String::from_utf8(buf).unwrap();
+        let members = container.members().into_iter().chain(self.all_members());
+        let separator = members.filter_map(|m| {
+            let key = m.key().unwrap();
+            let value = m.value().unwrap().span();
+            let between = &self.text[key.span.end..value.start];
+            if between.contains(|c| c == '\n' || c == '\r' || c == '#') {
+                None
+            } else {
+                Some(between)
+            }
+        }).next().unwrap_or(": ");
+        text.push_str(separator);
+        text.push_str(&try!(self.format_value(value, container, indent)));
+        Ok(text)
+    }
+
+    /// Returns every member in the document.
+    fn all_members(&self) -> Vec<&Node> {
+        fn collect<'a>(node: &'a Node, members: &mut Vec<&'a Node>) {
+            if node.kind == NodeKind::Member { members.push(node); }
+            for child in &node.children {
+                if let Element::Node(ref node) = *child { collect(node, members); }
+            }
+        }
+        let mut members = Vec::new();
+        collect(&self.root, &mut members);
+        members
+    }
+
+    fn insert_splices(&self, container: &Node, step: PathItem,
+                      value: &repr::Atom) -> EditResult<Vec<Splice>> {
+        let items = item_indices(container);
+        let at = match step {
+            PathItem::Key(_) if container.kind == NodeKind::Object => items.len(),
+            PathItem::Index(index) if container.kind == NodeKind::Array &&
+                                      index <= items.len() => index,
+            _ => { return Err(EditError::NotFound); }
+        };
+        let newline = self.writer_options(container).newline;
+
+        if items.is_empty() {
+            // `{}` or `[]`, possibly with trivia between the brackets
+            let outer = self.indent_at(container.span.start).to_string();
+            let indent = format!("{}{}", outer, self.indent_unit());
+            let item = try!(self.format_item(container, step, value, &indent));
+            let inner = Span::new(container.span.start + 1, container.span.end - 1);
+            if self.slice(inner).trim().is_empty() {
+                return Ok(vec![(inner, format!("{}{}{}{}{}", newline, indent, item,
+                                               newline, outer))]);
+            }
+            // keep comments between the brackets
+            return Ok(if self.starts_line(inner.end) {
+                let line = self.line_start(inner.end);
+                vec![(Span::new(line, line), format!("{}{}{}", indent, item, newline))]
+            } else {
+                vec![(Span::new(inner.end, inner.end),
+                      format!("{}{}{}{}{}", newline, indent, item, newline, outer))]
+            });
+        }
+
+        let braceless = !container.is_braced();
+        let comma_style = self.writer_options(container).separator == writer::Separator::Comma;
+        if at < items.len() {
+            // insert before an existing item
+            let next = container.children[items[at]].span();
+            if braceless || self.starts_line(next.start) {
+                let indent = self.indent_at(next.start).to_string();
+                let item = try!(self.format_item(container, step, value, &indent));
+                let comma = if comma_style { "," } else { "" };
+                let line = self.line_start(next.start);
+                return Ok(vec![(Span::new(line, line),
+                                format!("{}{}{}{}", indent, item, comma, newline))]);
+            } else {
+                let item = try!(self.format_item(container, step, value, ""));
+                return Ok(vec![(Span::new(next.start, next.start), format!("{}, ", item))]);
+            }
+        }
+
+        let last = container.children[*items.last().unwrap()].span();
+        if braceless || self.starts_line(last.start) {
+            let indent = self.indent_at(last.start).to_string();
+            let item = try!(self.format_item(container, step, value, &indent));
+            let (comma, end) = self.line_rest(last.end);
+            let mut splices = Vec::new();
+            let mut text = format!("{}{}{}", newline, indent, item);
+            if comma.is_some() {
+                text.push(',');
+            } else if comma_style {
+                splices.push((Span::new(last.end, last.end), ",".to_string()));
+            }
+            splices.push((Span::new(end, end), text));
+            Ok(splices)
+        } else {
+            let item = try!(self.format_item(container, step, value, ""));
+            Ok(vec![(Span::new(last.end, last.end), format!(", {}", item))])
+        }
+    }
+
+    fn remove_splices(&self, container: &Node, index: usize) -> Vec<Splice> {
+        let items = item_indices(container);
+        let k = items.iter().position(|&i| i == index).unwrap();
+        let item = container.children[index].span();
+        let braceless = !container.is_braced();
+        if braceless && items.len() == 1 {
+            // an empty braceless document is not a document
+            return vec![(container.span, "{}".to_string())];
+        }
+
+        let (comma, end) = self.line_rest(item.end);
+        let next = self.token_after(end);
+        let own_line = self.starts_line(item.start) &&
+                       next.map_or(true, |t| t.kind == TokenKind::Newline);
+        if own_line {
+            let line = self.line_start(item.start);
+            let end = match next {
+                Some(newline) => newline.span.end,
+                None => end,
+            };
+            let mut splices = vec![(Span::new(line, end), String::new())];
+            if k + 1 == items.len() && k > 0 && comma.is_none() {
+                // the previous item no longer needs a comma
+                let prev = container.children[items[k - 1]].span();
+                if let (Some(comma), _) = self.line_rest(prev.end) {
+                    splices.push((comma, String::new()));
+                }
+            }
+            return splices;
+        }
+
+        if k + 1 < items.len() {
+            let next = container.children[items[k + 1]].span();
+            vec![(Span::new(item.start, next.start), String::new())]
+        } else if k > 0 {
+            let prev = container.children[items[k - 1]].span();
+            vec![(Span::new(prev.end, item.end), String::new())]
+        } else {
+            let end = comma.map_or(item.end, |comma| comma.end);
+            vec![(Span::new(item.start, end), String::new())]
+        }
+    }
+
+    /// Applies non-overlapping splices and parses the result again.
+    fn apply(&mut self, mut splices: Vec<Splice>) -> EditResult<()> {
+        splices.sort_by(|a, b| b.0.start.cmp(&a.0.start));
+        let mut text = self.text.clone();
+        for (span, replacement) in splices {
+            text = format!("{}{}{}", &text[..span.start], replacement, &text[span.end..]);
+        }
+        let options = self.options.clone();
+        *self = try!(Document::parse_with_options(&text, options).map_err(EditError::Reparse));
+        Ok(())
+    }
+}
+
+/// Maps byte offsets in a text to positions without scanning the text every time.
+struct LineMap<'a> {
+    text: &'a str,
+    /// The starting offset of each line.
+    starts: Vec<usize>,
+}
+
+impl<'a> LineMap<'a> {
+    fn new(text: &'a str) -> LineMap<'a> {
+        let bytes = text.as_bytes();
+        let mut starts = vec![0];
+        for (i, &b) in bytes.iter().enumerate() {
+            match b {
+                b'\r' if bytes.get(i + 1) == Some(&b'\n') => {}
+                b'\n' | b'\r' => { starts.push(i + 1); }
+                _ => {}
+            }
+        }
+        LineMap { text: text, starts: starts }
+    }
+
+    fn position(&self, offset: usize) -> Position {
+        let bytes = self.text.as_bytes();
+        if offset > 0 && bytes[offset - 1] == b'\r' && bytes.get(offset) == Some(&b'\n') {
+            // `%x0D` alone already counts as a newline
+            return Position { offset: offset, ..self.position(offset + 1) };
+        }
+        let line = match self.starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        let start = self.starts[line];
+        let column = self.text[start..offset].chars().count() + 1;
+        Position { line: line + 1, column: column, offset: offset }
+    }
+}
+
+/// Returns the value of a member, or the element itself otherwise.
+fn item_value(element: &Element) -> &Element {
+    match *element {
+        Element::Node(ref node) if node.kind == NodeKind::Member => node.value().unwrap(),
+        _ => element,
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for token in self.root.tokens() {
+            try!(f.write_str(self.slice(token.span)));
+        }
+        Ok(())
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    tokens: Vec<Token>,
+    next: usize,
+    depth: usize,
+    options: &'a Options,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<TokenKind> {
+        self.tokens.get(self.next).map(|t| t.kind)
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens.get(self.next).map_or(self.text.len(), |t| t.span.start)
+    }
+
+    fn bump(&mut self, children: &mut Vec<Element>) {
+        children.push(Element::Token(self.tokens[self.next]));
+        self.next += 1;
+    }
+
+    fn err_at<T>(&self, offset: usize, kind: ErrorKind, cause: String) -> ReaderResult<T> {
+        Err(ReaderError { kind: kind, cause: cause.into(), ioerr: None,
+                          pos: Some(Position::from_offset(self.text.as_bytes(), offset)) })
+    }
+
+    fn err<T>(&self, cause: &str) -> ReaderResult<T> {
+        let cause = match self.tokens.get(self.next) {
+            Some(&Token { kind: TokenKind::Error, span }) => {
+                match &self.text[span.start..span.end] {
+                    s if s.starts_with('"') || s.starts_with('\'') => "incomplete string literal",
+                    s if s.starts_with('-') || s.starts_with(|c| '0' <= c && c <= '9') =>
+                        "incomplete number",
+                    _ => "unexpected character",
+                }
+            }
+            _ => cause,
+        };
+        self.err_at(self.offset(), ErrorKind::Syntax, cause.to_string())
+    }
+
+    /// Consumes trivia. Returns true if there was a newline.
+    fn trivia(&mut self, children: &mut Vec<Element>) -> bool {
+        let mut newline = false;
+        while let Some(kind) = self.peek() {
+            if !kind.is_trivia() { break; }
+            newline |= kind == TokenKind::Newline;
+            self.bump(children);
+        }
+        newline
+    }
+
+    fn enter(&mut self) -> ReaderResult<()> {
+        if self.depth >= self.options.max_depth {
+            let cause = format!("exceeded the maximum nesting depth of {}",
+                                self.options.max_depth);
+            return self.err_at(self.offset(), ErrorKind::LimitExceeded(Limit::Depth), cause);
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn document(&mut self) -> ReaderResult<Node> {
+        let mut children = Vec::new();
+        self.trivia(&mut children);
+        let value = match self.peek() {
+            Some(TokenKind::BeginObject) => try!(self.object()),
+            Some(TokenKind::BeginArray) => try!(self.array()),
+            Some(_) => {
+                let offset = self.offset();
+                let mut items = Vec::new();
+                try!(self.enter());
+                try!(self.items(&mut items, true));
+                self.depth -= 1;
+                // the trailing trivia belongs to the document
+                while items.last().map_or(false, |e| e.is_trivia()) {
+                    items.pop();
+                    self.next -= 1;
+                }
+                Node::new(NodeKind::Object, items, offset)
+            }
+            None => { return self.err("expected document"); }
+        };
+        children.push(Element::Node(value));
+        self.trivia(&mut children);
+        if self.peek().is_some() { return self.err("expected end of file"); }
+        Ok(Node::new(NodeKind::Document, children, 0))
+    }
+
+    fn object(&mut self) -> ReaderResult<Node> {
+        let offset = self.offset();
+        let mut children = Vec::new();
+        try!(self.enter());
+        self.bump(&mut children);
+        self.trivia(&mut children);
+        try!(self.items(&mut children, true));
+        if self.peek() != Some(TokenKind::EndObject) { return self.err("expected `}`"); }
+        self.bump(&mut children);
+        self.depth -= 1;
+        Ok(Node::new(NodeKind::Object, children, offset))
+    }
+
+    fn array(&mut self) -> ReaderResult<Node> {
+        let offset = self.offset();
+        let mut children = Vec::new();
+        try!(self.enter());
+        self.bump(&mut children);
+        self.trivia(&mut children);
+        try!(self.items(&mut children, false));
+        if self.peek() != Some(TokenKind::EndArray) { return self.err("expected `]`"); }
+        self.bump(&mut children);
+        self.depth -= 1;
+        Ok(Node::new(NodeKind::Array, children, offset))
+    }
+
+    /// Parses members (if `members` is true) or values separated by `value-separator`,
+    /// with an optional trailing separator.
+    fn items(&mut self, children: &mut Vec<Element>, members: bool) -> ReaderResult<()> {
+        let mut count = 0;
+        loop {
+            if count > 0 {
+                let newline = self.trivia(children);
+                if self.peek() == Some(TokenKind::Comma) {
+                    self.bump(children);
+                    self.trivia(children);
+                } else if !newline {
+                    break;
+                }
+            }
+            let item = if members { try!(self.member_opt()) } else { try!(self.value_opt()) };
+            match item {
+                Some(item) => { children.push(item); count += 1; }
+                None => { break; }
+            }
+        }
+        Ok(())
+    }
+
+    fn member_opt(&mut self) -> ReaderResult<Option<Element>> {
+        let offset = self.offset();
+        let token = match self.tokens.get(self.next) {
+            Some(&token) => token,
+            None => { return Ok(None); }
+        };
+        match token.kind {
+            TokenKind::BareString | TokenKind::Literal => {}
+            TokenKind::QuotedString(_) => {}
+            // a number starting with `-` is also a valid bare string
+            TokenKind::Number if self.text[token.span.start..].starts_with('-') => {}
+            _ => { return Ok(None); }
+        }
+
+        let mut children = Vec::new();
+        self.bump(&mut children);
+        self.trivia(&mut children);
+        match self.peek() {
+            Some(TokenKind::Colon) | Some(TokenKind::Equals) => { self.bump(&mut children); }
+            _ => { return self.err("expected `:` or `=`"); }
+        }
+        self.trivia(&mut children);
+        match try!(self.value_opt()) {
+            Some(value) => { children.push(value); }
+            None => { return self.err("expected value"); }
+        }
+        Ok(Some(Element::Node(Node::new(NodeKind::Member, children, offset))))
+    }
+
+    fn value_opt(&mut self) -> ReaderResult<Option<Element>> {
+        let token = match self.tokens.get(self.next) {
+            Some(&token) => token,
+            None => { return Ok(None); }
+        };
+        match token.kind {
+            TokenKind::Literal | TokenKind::Number | TokenKind::QuotedString(_) => {
+                self.next += 1;
+                Ok(Some(Element::Token(token)))
+            }
+            TokenKind::BeginObject => self.object().map(|n| Some(Element::Node(n))),
+            TokenKind::BeginArray => self.array().map(|n| Some(Element::Node(n))),
+            TokenKind::VerbatimLine => Ok(Some(Element::Node(self.verbatim_string()))),
+            _ => Ok(None),
+        }
+    }
+
+    fn verbatim_string(&mut self) -> Node {
+        let offset = self.offset();
+        let mut children = Vec::new();
+        self.bump(&mut children);
+        // the string continues only if the next line after trivia is also a verbatim line;
+        // otherwise the newline belongs to the following separator.
+        while self.peek() == Some(TokenKind::Newline) {
+            let mut next = self.next;
+            while self.tokens.get(next).map_or(false, |t| t.kind.is_trivia()) { next += 1; }
+            if self.tokens.get(next).map(|t| t.kind) != Some(TokenKind::VerbatimLine) { break; }
+            while self.next <= next { self.bump(&mut children); }
+        }
+        Node::new(NodeKind::VerbatimString, children, offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Document, NodeKind, Element, TokenKind, Quote, EditError};
+    use super::PathItem::{Key, Index};
+    use repr;
+    use reader::{Reader, Position, Options, DuplicateKeys};
+    use writer;
+
+    #[allow(non_snake_case)] // make it look like a constructor
+    fn String(s: &str) -> repr::Atom<'static> { repr::OwnedString(s.to_string()) }
+
+    #[test]
+    fn test_round_trip() {
+        let inputs = [
+            "# leading comment\n{\n  a: 1,   # trailing\n\n  'b' = \"two\"\n}\n",
+            "a: 1\nb: [1, 2,\n   3,]  # done\r\nc: {}\r\n\n",
+            "\u{feff}[true, false, null, -1.5e3, 'x\\'y']",
+            "  text: |hello\n        |  world \n  # between\n        |!\n  next = -3\n",
+            "-1: -2\n-a: 'b'\n",
+            "[\r  |cr\r  |only\r]",
+        ];
+        for input in &inputs {
+            let doc = Document::parse(input).unwrap();
+            assert_eq!(&doc.to_string(), input);
+            assert_eq!(doc.root().span.end, input.len());
+            assert_eq!(doc.to_atom().unwrap(),
+                       Reader::parse_document_from_buf(input.as_bytes()).unwrap());
+        }
+
+        let doc = Document::parse("# x\n{a: 'q', b = [1, |v\n]}  # y\n").unwrap();
+        let object = doc.root().root_value().unwrap();
+        assert_eq!(object.kind, NodeKind::Object);
+        assert!(object.is_braced());
+        let members = object.members();
+        assert_eq!(members.len(), 2);
+        assert_eq!(doc.slice(members[0].key().unwrap().span), "a");
+        match members[0].value() {
+            Some(&Element::Token(token)) => {
+                assert_eq!(token.kind, TokenKind::QuotedString(Quote::Single));
+            }
+            value => panic!("unexpected value {:?}", value),
+        }
+        let array = members[1].value().unwrap();
+        assert_eq!(doc.element_to_atom(array).unwrap(),
+                   repr::Array(vec![repr::I64(1), repr::OwnedString("v".to_string())]));
+
+        let doc = Document::parse("a: 1\n# trailing\n").unwrap();
+        let object = doc.root().root_value().unwrap();
+        assert!(!object.is_braced());
+        assert_eq!(doc.slice(object.span), "a: 1");
+        assert_eq!(doc.node_to_atom(object).unwrap(),
+                   doc.to_atom().unwrap());
+
+        let err = Document::parse("a: [1,\n  'x\\q']").unwrap_err();
+        assert_eq!((err.pos.unwrap().line, err.pos.unwrap().column), (2, 7));
+        assert!(Document::parse("a: 1 b: 2").is_err());
+        assert!(Document::parse("a: 'open").is_err());
+        assert!(Document::parse("").is_err());
+
+        let with = |text: &str, options: Options| Document::parse_with_options(text, options);
+        let strict = Options { strict_json: true, ..Options::default() };
+        assert!(with("{\"a\": [1]}", strict.clone()).is_ok());
+        assert!(with("a: [1]", strict.clone()).is_err());
+        assert!(with("{'a': 1}", strict).is_err());
+        let options = Options { duplicate_keys: DuplicateKeys::Error, ..Options::default() };
+        assert!(with("a: 1\na: 2", options).is_err());
+        assert!(with("a: [1]", Options { max_depth: 1, ..Options::default() }).is_err());
+        let options = Options { duplicate_keys: DuplicateKeys::FirstWins, ..Options::default() };
+        let mut doc = with("a: 1\na: 2\n", options).unwrap();
+        doc.insert(&[Key("b")], &repr::I64(3)).unwrap();
+        assert_eq!(doc.options().duplicate_keys, DuplicateKeys::FirstWins);
+        assert_eq!(doc.to_atom().unwrap(),
+                   Reader::parse_document_from_buf(b"a: 1\nb: 3").unwrap());
+
+        // numbers are kept as written, including radix prefixes
+        let options = Options { extended_numbers: true, ..Options::default() };
+        let mut doc = with("mask: 0xff\nsize: 1_024\n", options).unwrap();
+        doc.set(&[Key("size")], &repr::I64(2048)).unwrap();
+        assert_eq!(doc.text(), "mask: 0xff\nsize: 2048\n");
+        assert_eq!(doc.to_atom().unwrap(),
+                   Reader::parse_document_from_buf(b"mask: 255\nsize: 2048").unwrap());
+        assert!(Document::parse("mask: 0xff").is_err());
+    }
+
+    #[test]
+    fn test_edit() {
+        let text = "# deploy settings\n\
+                    version = '1.2.0'  # bumped by CI\n\
+                    \n\
+                    flags = {\n\
+                    \tfast = true,   # be quick\n\
+                    \t'old-name' = false,\n\
+                    }\n\
+                    hosts = ['a', 'b']\n";
+        let mut doc = Document::parse(text).unwrap();
+
+        doc.set(&[Key("version")], &String("1.3.0")).unwrap();
+        assert_eq!(doc.text(), &text.replace("1.2.0", "1.3.0")[..]);
+
+        doc.set(&[Key("flags"), Key("fast")], &repr::False).unwrap();
+        doc.insert(&[Key("flags"), Key("new flag")], &repr::I64(3)).unwrap();
+        doc.rename(&[Key("flags"), Key("old-name")], "renamed").unwrap();
+        doc.insert(&[Key("hosts"), Index(2)], &String("c")).unwrap();
+        doc.set(&[Key("owner")], &repr::Array(vec![String("ops")])).unwrap();
+        assert_eq!(doc.text(), "# deploy settings\n\
+                                version = '1.3.0'  # bumped by CI\n\
+                                \n\
+                                flags = {\n\
+                                \tfast = false,   # be quick\n\
+                                \t'renamed' = false,\n\
+                                \t'new flag' = 3,\n\
+                                }\n\
+                                hosts = ['a', 'b', 'c']\n\
+                                owner = [\n\
+                                \t'ops'\n\
+                                ]\n");
+
+        doc.remove(&[Key("flags"), Key("fast")]).unwrap();
+        doc.remove(&[Key("hosts"), Index(0)]).unwrap();
+        doc.remove(&[Key("version")]).unwrap();
+        assert_eq!(doc.text(), "# deploy settings\n\
+                                \n\
+                                flags = {\n\
+                                \t'renamed' = false,\n\
+                                \t'new flag' = 3,\n\
+                                }\n\
+                                hosts = ['b', 'c']\n\
+                                owner = [\n\
+                                \t'ops'\n\
+                                ]\n");
+
+        let mut doc = Document::parse("a: [\n  1,\n  2\n]\nb: {}\n").unwrap();
+        doc.remove(&[Key("a"), Index(1)]).unwrap();
+        doc.insert(&[Key("b"), Key("x")], &repr::Null).unwrap();
+        doc.insert(&[Key("a"), Index(0)], &repr::I64(0)).unwrap();
+        assert_eq!(doc.text(), "a: [\n  0\n  1\n]\nb: {\n  x: null\n}\n");
+        assert_eq!(doc.to_atom().unwrap(),
+                   Reader::parse_document_from_buf(b"a: [0, 1], b: {x: null}").unwrap());
+
+        match doc.insert(&[Key("b"), Key("x")], &repr::Null) {
+            Err(EditError::DuplicateKey) => {}
+            ret => panic!("unexpected result {:?}", ret),
+        }
+        match doc.set(&[Key("c"), Key("d")], &repr::Null) {
+            Err(EditError::NotFound) => {}
+            ret => panic!("unexpected result {:?}", ret),
+        }
+        let before = doc.text().to_string();
+        doc.rename(&[Key("a")], "a").unwrap();
+        assert_eq!(doc.text(), before);
+        match doc.rename(&[Key("a")], "b") {
+            Err(EditError::DuplicateKey) => {}
+            ret => panic!("unexpected result {:?}", ret),
+        }
+        doc.remove(&[Key("a")]).unwrap();
+        doc.remove(&[Key("b")]).unwrap();
+        assert_eq!(doc.text(), "{}\n");
+
+        // the whole document is replaced without changing whether it is braced
+        let mut doc = Document::parse("# top\na: 1\nb: 2\n").unwrap();
+        let object = repr::Object(vec![(repr::Key::new("c"), repr::I64(3)),
+                                       (repr::Key::new("d"), repr::Null)].into_iter().collect());
+        doc.set(&[], &object).unwrap();
+        assert_eq!(doc.text(), "# top\nc: 3\nd: null\n");
+        let mut doc = Document::parse("{a: 1}\n").unwrap();
+        doc.set(&[], &object).unwrap();
+        assert_eq!(doc.text(), "{\n  c: 3\n  d: null\n}\n");
+    }
+
+    #[test]
+    fn test_spanned() {
+        let text = "name: '\u{d55c}\u{ae00}'\r\n\
+                    server: {\r\n\
+                    \x20 port: 70000\r\n\
+                    \x20 hosts: [\"a\", {\"b\": |x\r\n\
+                    \x20   |y\r\n\
+                    \x20 }]\r\n\
+                    }\r\n";
+        let doc = Document::parse(text).unwrap();
+        let spanned = doc.to_spanned().unwrap();
+        assert_eq!(spanned.to_atom(), doc.to_atom().unwrap());
+
+        let pos = |s: &repr::Spanned| ((s.start.line, s.start.column, s.start.offset),
+                                       (s.end.line, s.end.column, s.end.offset));
+        assert_eq!(pos(&spanned), ((1, 1, 0), (7, 2, 82)));
+        assert_eq!(pos(spanned.at(&[Key("name")]).unwrap()), ((1, 7, 6), (1, 11, 14)));
+        let port = spanned.at(&[Key("server"), Key("port")]).unwrap();
+        assert_eq!(port.to_atom(), repr::I64(70000));
+        assert_eq!(pos(port), ((3, 9, 35), (3, 14, 40)));
+        let &(ref key, _) = spanned.at(&[Key("server")]).unwrap().get_member("port").unwrap();
+        assert_eq!((key.start.line, key.start.column, key.end.column), (3, 3, 7));
+        let b = spanned.at(&[Key("server"), Key("hosts"), Index(1), Key("b")]).unwrap();
+        assert_eq!(pos(b), ((4, 22, 63), (5, 7, 73)));
+        assert!(spanned.at(&[Key("server"), Key("hosts"), Index(2)]).is_none());
+        assert!(spanned.at(&[Key("name"), Key("x")]).is_none());
+
+        let lines = super::LineMap::new(text);
+        for offset in 0..text.len() + 1 {
+            if text.is_char_boundary(offset) {
+                assert_eq!(lines.position(offset), Position::from_offset(text.as_bytes(), offset));
+            }
+        }
+    }
+
+    #[test]
+    fn test_annotated() {
+        let text = "# Settings\n\
+                    # for the UI\n\
+                    port: 8080  # must be < 65536\n\
+                    \n\
+                    # detached\n\
+                    \n\
+                    theme: {\n\
+                    \x20 # light or dark\n\
+                    \x20 name: 'dark', # default\n\
+                    \x20 colors: [\n\
+                    \x20   # main\n\
+                    \x20   |red\n\
+                    \x20   |ish\n\
+                    \x20   'blue' # accent\n\
+                    \x20 ]\n\
+                    }\n";
+        let doc = Document::parse(text).unwrap();
+        let annotated = doc.to_annotated().unwrap();
+        assert_eq!(annotated.to_atom(), doc.to_atom().unwrap());
+
+        let port = annotated.get("port").unwrap();
+        assert_eq!(port.comments_before, vec![" Settings".to_string(), " for the UI".to_string()]);
+        assert_eq!(port.comment_after, Some(" must be < 65536".to_string()));
+        let theme = annotated.get("theme").unwrap();
+        assert!(theme.comments_before.is_empty());
+        let name = theme.get("name").unwrap();
+        assert_eq!(name.comments_before, vec![" light or dark".to_string()]);
+        assert_eq!(name.comment_after, Some(" default".to_string()));
+        let colors = theme.get("colors").unwrap();
+        assert_eq!(colors.index(0).unwrap().comments_before, vec![" main".to_string()]);
+        assert_eq!(colors.index(0).unwrap().comment_after, None);
+        assert_eq!(colors.index(1).unwrap().comment_after, Some(" accent".to_string()));
+
+        let written = writer::annotated_to_string(&annotated, writer::Options::default()).unwrap();
+        assert_eq!(written, "# Settings\n\
+                             # for the UI\n\
+                             port: 8080 # must be < 65536\n\
+                             theme: {\n\
+                             \x20 # light or dark\n\
+                             \x20 name: \"dark\" # default\n\
+                             \x20 colors: [\n\
+                             \x20   # main\n\
+                             \x20   |red\n\
+                             \x20     |ish\n\
+                             \x20   \"blue\" # accent\n\
+                             \x20 ]\n\
+                             }\n");
+        assert_eq!(Document::parse(&written).unwrap().to_annotated().unwrap(), annotated);
+
+        let doc = Document::parse("# top\n[1, 2]  # end\n").unwrap();
+        let annotated = doc.to_annotated().unwrap();
+        assert_eq!(annotated.comments_before, vec![" top".to_string()]);
+        assert_eq!(annotated.comment_after, Some(" end".to_string()));
+        let written = writer::annotated_to_string(&annotated, writer::Options::default()).unwrap();
+        assert_eq!(written, "# top\n[\n  1\n  2\n] # end\n");
+    }
+}
@@ -0,0 +1,312 @@
+// This is a part of CSON-rust.
+// Written by Kang Seonghoon. See README.md for details.
+
+//! A lexer for CSON which keeps every byte of the input.
+//!
+//! Unlike `reader::Reader`, the lexer does not discard whitespace and comments:
+//! concatenating the spans of all tokens always gives the original input back.
+//! It never stops at invalid input; anything which cannot start a token becomes
+//! a `TokenKind::Error` token and the lexing continues after it.
+//!
+//! The lexer does not know the context, so a number starting with `-` may be also used as
+//! a bare key. It also does not validate escape sequences in quoted strings.
+//! Numbers may have radix prefixes and `_` separators as in `reader::Options::extended_numbers`,
+//! which the reader rejects unless enabled.
+
+use super::reader::{is_id_start, is_id_end, radix_of};
+
+/// A range of bytes in the input, from `start` (inclusive) to `end` (exclusive).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start: start, end: end }
+    }
+
+    pub fn len(&self) -> usize { self.end - self.start }
+    pub fn is_empty(&self) -> bool { self.start == self.end }
+}
+
+/// A quotation mark used for a quoted string.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Quote {
+    /// `"`.
+    Double,
+    /// `'`.
+    Single,
+}
+
+/// A kind of tokens.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TokenKind {
+    /// One or more spaces and tabs. A UTF-8 BOM at the very beginning also counts.
+    Whitespace,
+    /// `%x0A`, `%x0D` or `%x0D %x0A`.
+    Newline,
+    /// `#` followed by the rest of the line.
+    Comment,
+    /// `{`.
+    BeginObject,
+    /// `}`.
+    EndObject,
+    /// `[`.
+    BeginArray,
+    /// `]`.
+    EndArray,
+    /// `,`.
+    Comma,
+    /// `:`.
+    Colon,
+    /// `=`.
+    Equals,
+    /// `false`, `null` or `true`.
+    Literal,
+    /// A number. A number starting with `-` can be also used as a bare key.
+    Number,
+    /// A quoted string including the quotes and escape sequences as written.
+    QuotedString(Quote),
+    /// `|` followed by the rest of the line, which is one line of a verbatim string.
+    VerbatimLine,
+    /// A bare string, which can be only used as a key.
+    BareString,
+    /// A character or an incomplete token which cannot start any valid token.
+    Error,
+}
+
+impl TokenKind {
+    /// Returns true if the token has no meaning other than the layout.
+    pub fn is_trivia(&self) -> bool {
+        match *self {
+            TokenKind::Whitespace | TokenKind::Newline | TokenKind::Comment => true,
+            _ => false,
+        }
+    }
+}
+
+/// A token with its span in the input.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+impl Token {
+    /// Returns the text of the token in the input `text`.
+    pub fn text<'a>(&self, text: &'a str) -> &'a str {
+        &text[self.span.start..self.span.end]
+    }
+}
+
+/// An iterator over tokens in a string.
+pub struct Lexer<'a> {
+    text: &'a str,
+    offset: usize,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(text: &'a str) -> Lexer<'a> {
+        Lexer { text: text, offset: 0 }
+    }
+
+    /// Returns the byte offset of the next token.
+    pub fn offset(&self) -> usize { self.offset }
+}
+
+/// Returns the end of a run of bytes from `start` satisfying `pred`.
+fn scan_bytes<F: Fn(u8) -> bool>(bytes: &[u8], start: usize, pred: F) -> usize {
+    let mut i = start;
+    while i < bytes.len() && pred(bytes[i]) { i += 1; }
+    i
+}
+
+/// Returns the end of `bare-string` which starts at `start`.
+fn scan_bare_string(text: &str, start: usize) -> usize {
+    let mut chars = text[start..].char_indices();
+    match chars.next() {
+        Some((_, c)) if is_id_start(c) => {}
+        _ => { return start; }
+    }
+    for (i, c) in chars {
+        if !is_id_end(c) { return start + i; }
+    }
+    text.len()
+}
+
+/// Returns the end of digits in `radix` from `start`, which may be separated by `_`.
+/// Returns `start` if there is no digit at `start`.
+fn scan_digits(bytes: &[u8], start: usize, radix: u32) -> usize {
+    let is_digit = |b: u8| (b as char).is_digit(radix);
+    match bytes.get(start) {
+        Some(&b) if is_digit(b) => scan_bytes(bytes, start + 1, |b| is_digit(b) || b == b'_'),
+        _ => start,
+    }
+}
+
+/// Scans a number from `start` in the same way as `reader::Reader` does
+/// with `reader::Options::extended_numbers`.
+///
+/// Returns the end of the longest prefix which looks like a number,
+/// and whether that prefix is a complete number.
+fn scan_number(bytes: &[u8], start: usize) -> (usize, bool) {
+    let mut i = start;
+    if bytes[i] == b'-' { i += 1; }
+    match bytes.get(i) {
+        Some(&b'0') => {
+            i += 1;
+            match bytes.get(i) {
+                Some(&b'.') | Some(&b'e') | Some(&b'E') => {}
+                Some(&b) if radix_of(Some(b)).is_some() => {
+                    let end = scan_digits(bytes, i + 1, radix_of(Some(b)).unwrap());
+                    return (end, end > i + 1);
+                }
+                _ => { return (i, true); }
+            }
+        }
+        Some(&b) if b.is_ascii_digit() => { i = scan_digits(bytes, i, 10); }
+        _ => { return (i, false); }
+    }
+    if bytes.get(i) == Some(&b'.') {
+        i += 1;
+        let end = scan_digits(bytes, i, 10);
+        if end == i { return (i, false); }
+        i = end;
+    }
+    match bytes.get(i) {
+        Some(&b'e') | Some(&b'E') => {
+            i += 1;
+            match bytes.get(i) {
+                Some(&b'-') | Some(&b'+') => { i += 1; }
+                _ => {}
+            }
+            let end = scan_digits(bytes, i, 10);
+            if end == i { return (i, false); }
+            i = end;
+        }
+        _ => {}
+    }
+    (i, true)
+}
+
+/// Returns the end of a quoted string starting at `start`, or `None` if it is not closed.
+fn scan_quoted_string(bytes: &[u8], start: usize) -> Option<usize> {
+    let quote = bytes[start];
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => { i += 2; }
+            b if b == quote => { return Some(i + 1); }
+            _ => { i += 1; }
+        }
+    }
+    None
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        let text = self.text;
+        let bytes = text.as_bytes();
+        let start = self.offset;
+        if start >= bytes.len() { return None; }
+
+        let non_newline = |b: u8| b != b'\n' && b != b'\r';
+        let (kind, end) = match bytes[start] {
+            b' ' | b'\t' => {
+                (TokenKind::Whitespace, scan_bytes(bytes, start, |b| b == b' ' || b == b'\t'))
+            }
+            b'\r' if bytes.get(start + 1) == Some(&b'\n') => (TokenKind::Newline, start + 2),
+            b'\n' | b'\r' => (TokenKind::Newline, start + 1),
+            b'#' => (TokenKind::Comment, scan_bytes(bytes, start, non_newline)),
+            b'|' => (TokenKind::VerbatimLine, scan_bytes(bytes, start, non_newline)),
+            b'{' => (TokenKind::BeginObject, start + 1),
+            b'}' => (TokenKind::EndObject, start + 1),
+            b'[' => (TokenKind::BeginArray, start + 1),
+            b']' => (TokenKind::EndArray, start + 1),
+            b',' => (TokenKind::Comma, start + 1),
+            b':' => (TokenKind::Colon, start + 1),
+            b'=' => (TokenKind::Equals, start + 1),
+            b'"' | b'\'' => {
+                let quote = if bytes[start] == b'"' { Quote::Double } else { Quote::Single };
+                match scan_quoted_string(bytes, start) {
+                    Some(end) => (TokenKind::QuotedString(quote), end),
+                    None => (TokenKind::Error, bytes.len()),
+                }
+            }
+            b'-' | b'0'...b'9' => {
+                // `-` starts both a number and a bare string; the longer one wins.
+                let (end, complete) = scan_number(bytes, start);
+                let bare_end = scan_bare_string(text, start);
+                if bare_end > start && (bare_end > end || !complete) {
+                    (TokenKind::BareString, bare_end)
+                } else if complete {
+                    (TokenKind::Number, end)
+                } else {
+                    (TokenKind::Error, end)
+                }
+            }
+            _ if start == 0 && text.starts_with('\u{feff}') => (TokenKind::Whitespace, 3),
+            _ => {
+                let end = scan_bare_string(text, start);
+                if end > start {
+                    match &text[start..end] {
+                        "false" | "null" | "true" => (TokenKind::Literal, end),
+                        _ => (TokenKind::BareString, end),
+                    }
+                } else {
+                    let c = text[start..].chars().next().unwrap();
+                    (TokenKind::Error, start + c.len_utf8())
+                }
+            }
+        };
+
+        self.offset = end;
+        Some(Token { kind: kind, span: Span::new(start, end) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Lexer, TokenKind, Quote};
+    use super::TokenKind::*;
+
+    fn lex(text: &str) -> Vec<(TokenKind, &str)> {
+        Lexer::new(text).map(|t| (t.kind, t.text(text))).collect()
+    }
+
+    #[test]
+    fn test_lexer() {
+        assert_eq!(lex("a: [1, -2.5e3]  # c\r\n'b'=\"\\\"\"\n|x y\n"),
+                   vec![(BareString, "a"), (Colon, ":"), (Whitespace, " "), (BeginArray, "["),
+                        (Number, "1"), (Comma, ","), (Whitespace, " "), (Number, "-2.5e3"),
+                        (EndArray, "]"), (Whitespace, "  "), (Comment, "# c"), (Newline, "\r\n"),
+                        (QuotedString(Quote::Single), "'b'"), (Equals, "="),
+                        (QuotedString(Quote::Double), "\"\\\"\""), (Newline, "\n"),
+                        (VerbatimLine, "|x y"), (Newline, "\n")]);
+        assert_eq!(lex("{true nullable -key -1x 01}"),
+                   vec![(BeginObject, "{"), (Literal, "true"), (Whitespace, " "),
+                        (BareString, "nullable"), (Whitespace, " "), (BareString, "-key"),
+                        (Whitespace, " "), (BareString, "-1x"), (Whitespace, " "),
+                        (Number, "0"), (Number, "1"), (EndObject, "}")]);
+        assert_eq!(lex("[0xff, -0b1_0, 1_000.5, 0x]"),
+                   vec![(BeginArray, "["), (Number, "0xff"), (Comma, ","), (Whitespace, " "),
+                        (Number, "-0b1_0"), (Comma, ","), (Whitespace, " "),
+                        (Number, "1_000.5"), (Comma, ","), (Whitespace, " "),
+                        (Error, "0x"), (EndArray, "]")]);
+
+        // invalid input does not stop the lexer
+        assert_eq!(lex("1. ~ @\r'open"),
+                   vec![(Error, "1."), (Whitespace, " "), (Error, "~"), (Whitespace, " "),
+                        (Error, "@"), (Newline, "\r"), (Error, "'open")]);
+
+        let text = "\u{feff}# \u{ac00}\n\u{3042}: |\u{2603}\n";
+        let tokens: Vec<_> = Lexer::new(text).collect();
+        assert_eq!(tokens[0].kind, Whitespace);
+        assert_eq!(tokens.iter().map(|t| t.text(text)).collect::<String>(), text);
+        assert!(tokens.windows(2).all(|w| w[0].span.end == w[1].span.start));
+    }
+}
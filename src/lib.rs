@@ -24,6 +24,9 @@ extern crate rustc_serialize as serialize;
 
 pub mod repr;
 pub mod reader;
-//mod writer;
+pub mod writer;
+pub mod lexer;
+pub mod cst;
+pub mod coffee;
 
 mod util;
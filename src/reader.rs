@@ -1,41 +1,255 @@
 // This is a part of CSON-rust.
 // Written by Kang Seonghoon. See README.md for details.
 
-use std::{str, fmt};
+use std::{str, fmt, cmp, mem};
 use std::borrow::Cow;
-use std::collections::BTreeMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::rc::Rc;
 use std::io;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Read};
 use super::repr;
-use super::repr::Key;
+use super::repr::{Key, advance_position};
 use super::util;
+use super::util::encoding::{Decoder, Detected};
+
+/// The position type is shared with `repr`, and re-exported here for compatibility.
+pub use super::repr::Position;
 
 #[cfg(test)] use std::char;
 
+/// The kind of a reader error.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ErrorKind {
+    /// The input stream has failed.
+    Io,
+    /// The input is not a valid CSON.
+    Syntax,
+    /// The input has exceeded one of the limits set in `Options`.
+    LimitExceeded(Limit),
+}
+
+/// A resource limit set in `Options`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Limit {
+    /// `Options::max_input_bytes`.
+    InputBytes,
+    /// `Options::max_depth`.
+    Depth,
+    /// `Options::max_string_len`.
+    StringLength,
+    /// `Options::max_number_len`.
+    NumberLength,
+    /// `Options::max_array_len`.
+    ArrayLength,
+    /// `Options::max_object_members`.
+    ObjectMembers,
+    /// `Options::max_nodes`.
+    Nodes,
+}
+
 #[derive(Debug)]
 pub struct ReaderError {
+    pub kind: ErrorKind,
     pub cause: Cow<'static, str>,
     pub ioerr: Option<io::Error>,
+    /// The position where the error has been detected, if known.
+    pub pos: Option<Position>,
 }
 
 impl fmt::Display for ReaderError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self.ioerr {
-            Some(ref ioerr) => write!(f, "{} ({})", self.cause, *ioerr),
-            None => write!(f, "{}", self.cause),
+        try!(write!(f, "{}", self.cause));
+        if let Some(ref pos) = self.pos {
+            try!(write!(f, " at {}", *pos));
+        }
+        if let Some(ref ioerr) = self.ioerr {
+            try!(write!(f, " ({})", *ioerr));
         }
+        Ok(())
     }
 }
 
 impl From<io::Error> for ReaderError {
     fn from(err: io::Error) -> ReaderError {
-        ReaderError { cause: "I/O error".into(), ioerr: Some(err) }
+        ReaderError { kind: ErrorKind::Io, cause: "I/O error".into(), ioerr: Some(err), pos: None }
     }
 }
 
 pub type ReaderResult<T> = Result<T, ReaderError>;
 
-fn is_id_start(c: char) -> bool {
+/// A non-fatal diagnostic found while reading.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ReaderWarning {
+    pub cause: Cow<'static, str>,
+    pub pos: Position,
+}
+
+impl fmt::Display for ReaderWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}", self.cause, self.pos)
+    }
+}
+
+/// What to do when an object contains the same key more than once.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DuplicateKeys {
+    /// Fails with an error referring to both occurrences. Suitable for strict validation.
+    Error,
+    /// Keeps the last value and records a warning.
+    Warn,
+    /// Keeps the first value and ignores the later ones.
+    FirstWins,
+    /// Keeps the last value. This is the default.
+    LastWins,
+    /// Keeps every value. Every key maps to an array of all its values in the source order,
+    /// even when the key occurs only once, so that a repeated key can be told apart from
+    /// an array value. Dotted keys are an error with this policy.
+    Collect,
+}
+
+/// Reader options.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Options {
+    /// The policy for repeated keys in an object.
+    pub duplicate_keys: DuplicateKeys,
+    /// The maximum number of nested objects and arrays, including the outermost one.
+    /// The parser is recursive, so this protects against a stack overflow. Defaults to 128.
+    pub max_depth: usize,
+
+    // The following limits are unlimited (`usize::MAX`) by default.
+    // They should be set when the input is not trusted.
+
+    /// The maximum number of bytes read from the input stream.
+    pub max_input_bytes: usize,
+    /// The maximum length of a string or a key in bytes, after processing escape sequences.
+    pub max_string_len: usize,
+    /// The maximum length of a number literal in bytes.
+    pub max_number_len: usize,
+    /// The maximum number of elements in an array.
+    pub max_array_len: usize,
+    /// The maximum number of members in an object, including repeated keys.
+    pub max_object_members: usize,
+    /// The maximum number of values in the document, including nested ones.
+    pub max_nodes: usize,
+
+    /// Accepts only a strict JSON as defined by RFC 8259 and rejects every CSON extension.
+    ///
+    /// This rejects bare keys, `=` separators, single-quoted strings, verbatim strings,
+    /// comments, newlines as value separators, trailing separators, braceless documents,
+    /// control characters inside strings and the `\'` escape sequence.
+    /// A document may be any value as in JSON.
+    pub strict_json: bool,
+
+    /// Detects UTF-16 and UTF-32 inputs and converts them to UTF-8.
+    ///
+    /// They are detected from the byte order mark, or from the pattern of null bytes when
+    /// the byte order mark is missing. Positions then refer to the input converted to UTF-8.
+    /// A UTF-8 byte order mark is always skipped regardless of this option.
+    pub detect_encoding: bool,
+
+    /// Replaces each invalid UTF-8 sequence in the input with U+FFFD instead of failing,
+    /// and records a warning with the raw byte offset of the sequence.
+    /// Positions, including those of the warnings, then refer to the input after
+    /// the replacement. This has no effect on UTF-16 and UTF-32 inputs.
+    pub lossy_utf8: bool,
+
+    /// Keeps the original line endings (`\n`, `\r\n` or `\r`) in strings, both between
+    /// fragments of a verbatim string and unescaped inside quoted strings.
+    /// They are normalized to `\n` by default, while escaped ones are always kept.
+    pub keep_line_endings: bool,
+
+    /// Records warnings for input which is valid but suspicious:
+    ///
+    /// * `,` and newlines mixed as separators in the same object or array.
+    /// * A duplicate key with `DuplicateKeys::LastWins`.
+    /// * A number whose digits do not survive the conversion to `F64`.
+    /// * Trailing whitespace in a line of a verbatim string, which is kept.
+    /// * Tabs in the indentation.
+    pub lints: bool,
+
+    // The following extensions to CSON are disabled by default.
+
+    /// Accepts `NaN`, `Infinity`, `+Infinity` and `-Infinity` as values.
+    pub nan_infinity: bool,
+    /// Accepts integers with `0x`, `0o` and `0b` prefixes, and `_` between digits of
+    /// any number as in `1_000_000`. Prefixed integers too large for `I64` or `U64`
+    /// are errors instead of being approximated as `F64`.
+    pub extended_numbers: bool,
+    /// Accepts `\u{XXXXXX}` with 1 to 6 hexadecimal digits, `\xHH` for ASCII characters,
+    /// `\0`, and a backslash followed by a newline which skips the newline and the leading
+    /// whitespace of the next line in quoted strings.
+    pub extended_escapes: bool,
+    /// Accepts the rest of JSON5 which is not covered by other options:
+    /// `//` and `/* */` comments, vertical tabs and form feeds as whitespace,
+    /// leading and trailing decimal points, `+` signs, `0x` integers, `\uXXXX` in bare keys,
+    /// and in quoted strings `\v`, `\0`, `\xHH` up to `\xFF`, line continuations and
+    /// any other character escaping itself. `NaN` may have a sign with `nan_infinity`.
+    ///
+    /// `Options::json5()` enables this along with `nan_infinity`. Other extensions such as
+    /// `0o` and `0b` integers or `_` between digits are not part of JSON5 and stay disabled.
+    pub json5: bool,
+    /// Accepts `bare-string` as a value, as in `mode = production`.
+    ///
+    /// `true`, `false` and `null` keep their meaning, as do `NaN`, `Infinity` and `-Infinity`
+    /// with `nan_infinity`. `-` followed by a digit still starts a number. Any other bare string
+    /// is a string, which ends at the first character not allowed in `bare-string`;
+    /// `mode = hello world` is still an error.
+    pub bare_values: bool,
+    /// Reads `.` in bare keys as a separator of nested keys, as in `server.tls.cert = "x"`
+    /// which is same to `server: {tls: {cert: "x"}}`. Quoted keys are not split, but
+    /// can be joined with `.` as in `hosts."example.com".port = 80`.
+    ///
+    /// Members sharing a prefix are merged into the same object. It is an error to extend
+    /// a key defined as anything other than an object, or to redefine an object created by
+    /// dotted keys. It is also an error to use dotted keys with `DuplicateKeys::Collect`,
+    /// where every key already maps to an array and there is no single object to extend.
+    pub dotted_keys: bool,
+    /// Accepts a tag before any value, as in `@date "2024-01-01"`. The tag is a bare string
+    /// after `@`, and the value becomes `Tagged`. If a converter for the tag has been
+    /// registered with `Reader::register_tag`, it validates or transforms the value first.
+    pub tags: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            duplicate_keys: DuplicateKeys::LastWins,
+            max_depth: 128,
+            max_input_bytes: usize::MAX,
+            max_string_len: usize::MAX,
+            max_number_len: usize::MAX,
+            max_array_len: usize::MAX,
+            max_object_members: usize::MAX,
+            max_nodes: usize::MAX,
+            strict_json: false,
+            detect_encoding: false,
+            lossy_utf8: false,
+            keep_line_endings: false,
+            lints: false,
+            nan_infinity: false,
+            extended_numbers: false,
+            extended_escapes: false,
+            json5: false,
+            bare_values: false,
+            dotted_keys: false,
+            tags: false,
+        }
+    }
+}
+
+impl Options {
+    /// Returns the default options with every extension required to read JSON5 enabled.
+    pub fn json5() -> Options {
+        Options {
+            nan_infinity: true,
+            json5: true,
+            ..Options::default()
+        }
+    }
+}
+
+pub(crate) fn is_id_start(c: char) -> bool {
     match c {
         '\u{24}' |
         '\u{2D}' |
@@ -98,7 +312,7 @@ fn test_is_id_start() {
     }
 }
 
-fn is_id_end(c: char) -> bool {
+pub(crate) fn is_id_end(c: char) -> bool {
     match c {
         '\u{24}' |
         '\u{2D}'...'\u{2E}' |
@@ -162,18 +376,331 @@ fn test_is_id_end() {
 }
 
 fn reader_err<T, Cause: Into<Cow<'static, str>>>(cause: Cause) -> ReaderResult<T> {
-    Err(ReaderError { cause: cause.into(), ioerr: None })
+    Err(ReaderError { kind: ErrorKind::Syntax, cause: cause.into(), ioerr: None, pos: None })
+}
+
+fn reader_err_at<T, Cause: Into<Cow<'static, str>>>(pos: Position,
+                                                    cause: Cause) -> ReaderResult<T> {
+    Err(ReaderError { kind: ErrorKind::Syntax, cause: cause.into(), ioerr: None,
+                      pos: Some(pos) })
+}
+
+fn limit_err<T, Cause: Into<Cow<'static, str>>>(limit: Limit, cause: Cause) -> ReaderResult<T> {
+    Err(ReaderError { kind: ErrorKind::LimitExceeded(limit), cause: cause.into(), ioerr: None,
+                      pos: None })
 }
 
 struct Newline;
 
+/// The result of an escape sequence with `Options::extended_escapes`.
+enum Escaped {
+    /// A character, which is never a surrogate.
+    Char(u32),
+    /// An UTF-16 code unit from `escaped_minus_escape`, which may be a surrogate.
+    Unit(u16),
+    /// A line continuation.
+    Nothing,
+}
+
+/// Appends a character to `bytes` as UTF-8.
+///
+/// This wouldn't affect the validness of other raw `bytes` as UTF-8 ensures that
+/// no valid sequence can made into invalid one or vice versa.
+fn push_char(bytes: &mut Vec<u8>, ch: u32) {
+    let mut charbuf = [0u8; 4];
+    let charbuflen = util::char::encode_utf8_raw(ch, &mut charbuf).unwrap();
+    bytes.extend(charbuf[..charbuflen].iter().map(|&b| b));
+}
+
+/// Returns the radix for a radix prefix after `0`.
+pub(crate) fn radix_of(prefix: Option<u8>) -> Option<u32> {
+    match prefix {
+        Some(b'x') | Some(b'X') => Some(16),
+        Some(b'o') | Some(b'O') => Some(8),
+        Some(b'b') | Some(b'B') => Some(2),
+        _ => None,
+    }
+}
+
+/// Returns a path of keys as written in dotted keys.
+fn join_path(path: &[Key]) -> String {
+    path.iter().map(|key| &**key).collect::<Vec<_>>().join(".")
+}
+
+/// Returns the significant digits and the decimal exponent of a decimal number literal,
+/// such that the number is `0.DIGITS * 10^EXPONENT`. Zero has no digits.
+/// Returns `None` if the exponent does not fit.
+fn decimal_digits(s: &str) -> Option<(String, i64)> {
+    let s = s.trim_left_matches(|c| c == '-' || c == '+');
+    let (mantissa, exp) = match s.find(|c| c == 'e' || c == 'E') {
+        Some(i) => match s[i + 1..].parse::<i64>() {
+            Ok(exp) => (&s[..i], exp),
+            Err(_) => { return None; }
+        },
+        None => (s, 0),
+    };
+    let (int, frac) = match mantissa.find('.') {
+        Some(i) => (&mantissa[..i], &mantissa[i + 1..]),
+        None => (mantissa, ""),
+    };
+    let digits = format!("{}{}", int, frac);
+    let trimmed = digits.trim_left_matches('0');
+    let exp = exp.checked_add(int.len() as i64 - (digits.len() - trimmed.len()) as i64);
+    let trimmed = trimmed.trim_right_matches('0');
+    if trimmed.is_empty() { return Some((String::new(), 0)); }
+    exp.map(|exp| (trimmed.to_string(), exp))
+}
+
+/// Returns true if `v` parsed from a decimal number literal `s` is not rounded,
+/// in the sense that the shortest representation of `v` has the same digits as `s`.
+fn is_exact_f64(s: &str, v: f64) -> bool {
+    if v.is_infinite() { return false; }
+    match decimal_digits(s) {
+        Some(digits) => decimal_digits(&format!("{:e}", v)) == Some(digits),
+        None => false,
+    }
+}
+
+/// Checks if every `_` in a number literal is placed between two digits.
+fn check_digit_separators(bytes: &[u8], radix: u32) -> ReaderResult<()> {
+    let is_digit = |b: Option<&u8>| b.map_or(false, |&b| (b as char).is_digit(radix));
+    for (i, &b) in bytes.iter().enumerate() {
+        if b == b'_' && !(i > 0 && is_digit(bytes.get(i - 1)) && is_digit(bytes.get(i + 1))) {
+            return reader_err("`_` in a number should be placed between digits");
+        }
+    }
+    Ok(())
+}
+
+/// A wrapper around the input stream which keeps track of the current position.
+///
+/// It also enforces `Options::max_input_bytes`: once `limit` bytes have been read,
+/// any further read fails unless the stream is at the end. `exceeded` is set in that case.
+struct Tracker<'a> {
+    inner: Box<BufRead + 'a>,
+    pos: Position,
+    after_cr: bool,
+    limit: usize,
+    exceeded: bool,
+}
+
+impl<'a> Tracker<'a> {
+    /// Returns the number of bytes that can be read further.
+    fn remaining(&mut self) -> io::Result<usize> {
+        let remaining = self.limit.saturating_sub(self.pos.offset);
+        if remaining == 0 && !try!(self.inner.fill_buf()).is_empty() {
+            self.exceeded = true;
+            return Err(io::Error::new(io::ErrorKind::Other, "input limit exceeded"));
+        }
+        Ok(remaining)
+    }
+}
+
+impl<'a> Read for Tracker<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let max = cmp::min(try!(self.remaining()), buf.len());
+        let read = try!(self.inner.read(&mut buf[..max]));
+        advance_position(&mut self.pos, &mut self.after_cr, &buf[..read]);
+        Ok(read)
+    }
+}
+
+impl<'a> BufRead for Tracker<'a> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let remaining = try!(self.remaining());
+        let buf = try!(self.inner.fill_buf());
+        Ok(&buf[..cmp::min(remaining, buf.len())])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if amt > 0 {
+            // the buffer has been already filled, so this does not cause any further I/O
+            if let Ok(buf) = self.inner.fill_buf() {
+                let amt = cmp::min(amt, buf.len());
+                advance_position(&mut self.pos, &mut self.after_cr, &buf[..amt]);
+            }
+        }
+        self.inner.consume(amt);
+    }
+}
+
+/// A wrapper around the input stream which replaces invalid UTF-8 sequences with U+FFFD,
+/// used for `Options::lossy_utf8`. It is placed below `Tracker`.
+///
+/// A warning for each replacement is released to `released` only when the replacement
+/// is consumed, so that the warnings do not run ahead of the parser.
+struct LossyUtf8<'a> {
+    inner: Box<BufRead + 'a>,
+    /// Raw bytes which do not make a complete character yet.
+    pending: Vec<u8>,
+    /// The raw byte offset of `pending`.
+    raw_offset: usize,
+    /// Decoded bytes, of which `out[outpos..]` have not been consumed yet.
+    out: Vec<u8>,
+    outpos: usize,
+    /// The position at the end of `out`.
+    pos: Position,
+    after_cr: bool,
+    /// Warnings for replacements in `out` along with their indices.
+    replaced: VecDeque<(usize, ReaderWarning)>,
+    released: Rc<RefCell<Vec<ReaderWarning>>>,
+}
+
+impl<'a> LossyUtf8<'a> {
+    fn new(inner: Box<BufRead + 'a>, offset: usize,
+           released: Rc<RefCell<Vec<ReaderWarning>>>) -> LossyUtf8<'a> {
+        let mut pos = Position::start();
+        pos.offset = offset;
+        LossyUtf8 { inner: inner, pending: Vec::new(), raw_offset: offset, out: Vec::new(),
+                    outpos: 0, pos: pos, after_cr: false, replaced: VecDeque::new(),
+                    released: released }
+    }
+
+    /// Decodes `pending` into `out` as much as possible.
+    /// An incomplete sequence at the end is kept unless `eof` is set.
+    fn decode_pending(&mut self, eof: bool) {
+        let mut i = 0;
+        loop {
+            let (valid, invalid) = match str::from_utf8(&self.pending[i..]) {
+                Ok(s) => (s.len(), None),
+                Err(e) => {
+                    let rest = self.pending.len() - i - e.valid_up_to();
+                    (e.valid_up_to(), e.error_len().or(if eof { Some(rest) } else { None }))
+                }
+            };
+            let valid = &self.pending[i..i + valid];
+            self.out.extend_from_slice(valid);
+            advance_position(&mut self.pos, &mut self.after_cr, valid);
+            i += valid.len();
+
+            let len = match invalid {
+                Some(len) if len > 0 => len,
+                _ => { break; }
+            };
+            let bytes: String = self.pending[i..i + len].iter()
+                .map(|b| format!("\\x{:02x}", b)).collect();
+            let cause = format!("invalid UTF-8 sequence `{}` at byte offset {} is replaced \
+                                 with U+FFFD", bytes, self.raw_offset + i);
+            self.replaced.push_back((self.out.len(),
+                                     ReaderWarning { cause: cause.into(), pos: self.pos }));
+            let replacement = "\u{fffd}".as_bytes();
+            self.out.extend_from_slice(replacement);
+            advance_position(&mut self.pos, &mut self.after_cr, replacement);
+            i += len;
+        }
+        self.pending.drain(..i);
+        self.raw_offset += i;
+    }
+}
+
+impl<'a> Read for LossyUtf8<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let avail = try!(self.fill_buf());
+            let n = cmp::min(avail.len(), buf.len());
+            buf[..n].copy_from_slice(&avail[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<'a> BufRead for LossyUtf8<'a> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.outpos >= self.out.len() {
+            self.out.clear();
+            self.outpos = 0;
+            let len = {
+                let raw = try!(self.inner.fill_buf());
+                self.pending.extend_from_slice(raw);
+                raw.len()
+            };
+            self.inner.consume(len);
+            self.decode_pending(len == 0);
+            if len == 0 { break; }
+        }
+        Ok(&self.out[self.outpos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.outpos = cmp::min(self.outpos + amt, self.out.len());
+        while self.replaced.front().map_or(false, |&(i, _)| i < self.outpos) {
+            let (_, warning) = self.replaced.pop_front().unwrap();
+            self.released.borrow_mut().push(warning);
+        }
+    }
+}
+
+/// A callback set by `Reader::set_warning_callback`.
+type WarningCallback<'a> = Box<FnMut(&ReaderWarning) + 'a>;
+
+/// A converter registered by `Reader::register_tag`.
+type TagConverter<'a> = Box<FnMut(repr::Atom<'static>) -> Result<repr::Atom<'static>, String> + 'a>;
+
 pub struct Reader<'a> {
-    buf: &'a mut (BufRead + 'a),
+    buf: Tracker<'a>,
+    options: Options,
+    warnings: Vec<ReaderWarning>,
+    /// Warnings from `LossyUtf8`, which are moved to `warnings` as soon as possible.
+    replaced: Rc<RefCell<Vec<ReaderWarning>>>,
+    depth: usize,
+    nodes: usize,
+    started: bool,
+    /// True when a verbatim string has consumed a newline which should be seen by `skip_ws`.
+    pending_newline: bool,
+    /// Separators seen in each nested object or array, for `Options::lints`.
+    separators: Vec<SeparatorKinds>,
+    on_warning: Option<WarningCallback<'a>>,
+    /// Converters for tagged values, for `Options::tags`.
+    tags: BTreeMap<String, TagConverter<'a>>,
+}
+
+/// Kinds of `value-separator` seen in an object or an array.
+#[derive(Clone, Copy, Default)]
+struct SeparatorKinds {
+    comma: bool,
+    newline: bool,
+    /// True when the mixed separators have been already warned.
+    warned: bool,
 }
 
 impl<'a> Reader<'a> {
     pub fn new<T: BufRead>(buf: &'a mut T) -> Reader<'a> {
-        Reader { buf: buf }
+        Reader::with_options(buf, Options::default())
+    }
+
+    pub fn with_options<T: BufRead>(buf: &'a mut T, options: Options) -> Reader<'a> {
+        let limit = options.max_input_bytes;
+        Reader {
+            buf: Tracker { inner: Box::new(buf), pos: Position::start(), after_cr: false,
+                           limit: limit, exceeded: false },
+            options: options,
+            warnings: Vec::new(),
+            replaced: Rc::new(RefCell::new(Vec::new())),
+            depth: 0,
+            nodes: 0,
+            started: false,
+            pending_newline: false,
+            separators: Vec::new(),
+            on_warning: None,
+            tags: BTreeMap::new(),
+        }
+    }
+
+    /// Sets a callback which is called for every warning as soon as it is recorded.
+    /// Warnings are still available from `Reader::warnings` as well.
+    pub fn set_warning_callback<F: FnMut(&ReaderWarning) + 'a>(&mut self, callback: F) {
+        self.on_warning = Some(Box::new(callback));
+    }
+
+    /// Registers a converter for values tagged with `tag`, replacing any previous one.
+    /// The converted value is still wrapped in `Tagged`, and an error from the converter
+    /// is reported at the start of the value. Tags without converters are kept as is.
+    pub fn register_tag<F>(&mut self, tag: &str, converter: F)
+        where F: FnMut(repr::Atom<'static>) -> Result<repr::Atom<'static>, String> + 'a
+    {
+        self.tags.insert(tag.to_string(), Box::new(converter));
     }
 
     pub fn parse_document_from_buf(buf: &[u8]) -> ReaderResult<repr::Atom<'static>> {
@@ -184,14 +711,179 @@ impl<'a> Reader<'a> {
         Reader::new(&mut BufReader::new(buf)).parse_value()
     }
 
-    pub fn parse_document(mut self) -> ReaderResult<repr::Atom<'static>> {
+    /// Parses a whole document.
+    ///
+    /// This borrows the reader instead of consuming it, so that `Reader::warnings` remains
+    /// available afterwards. Note that this is a breaking change from earlier versions.
+    pub fn parse_document(&mut self) -> ReaderResult<repr::Atom<'static>> {
+        let ret = self.document_then_eof();
+        self.locate(ret)
+    }
+
+    /// Parses a single value. This borrows the reader as `Reader::parse_document` does.
+    pub fn parse_value(&mut self) -> ReaderResult<repr::Atom<'static>> {
+        let ret = self.value_then_eof();
+        self.locate(ret)
+    }
+
+    /// Returns an iterator over consecutive documents in the stream.
+    ///
+    /// Documents may be separated by any whitespace and comments, and also by
+    /// the record separator (U+001E) as in JSON text sequences (RFC 7464).
+    /// A document in brackets ends at its closing bracket. A braceless document extends as far
+    /// as possible, and should be followed by the end of stream, a record separator or
+    /// the next document in brackets; use a record separator between two braceless documents.
+    /// In the strict JSON mode every document is a value followed by whitespace.
+    ///
+    /// Every error is positioned relative to the whole stream, and ends the iteration.
+    pub fn documents<'r>(&'r mut self) -> Documents<'r, 'a> {
+        Documents { reader: self, done: false }
+    }
+
+    /// Returns an iterator over elements of the top-level array, which are yielded as soon as
+    /// they are parsed. The memory usage is thus proportional to the largest element.
+    ///
+    /// The stream should contain a single array. `Options::max_nodes` applies to each element.
+    /// An error ends the iteration.
+    pub fn elements<'r>(&'r mut self) -> Elements<'r, 'a> {
+        Elements { reader: self, state: StreamState::Start }
+    }
+
+    /// Returns an iterator over members of the top-level object, which is either in braces or
+    /// braceless, and yields them as soon as they are parsed. See also `Reader::elements`.
+    ///
+    /// Every member is yielded in the source order, even when the key is repeated.
+    /// In the other words, `Options::duplicate_keys` is not used.
+    /// Likewise, a member with `Options::dotted_keys` is yielded as its first key and
+    /// nested objects for the rest, which are not merged with other members.
+    pub fn members<'r>(&'r mut self) -> Members<'r, 'a> {
+        Members { reader: self, state: StreamState::Start }
+    }
+
+    /// Returns the current position in the input stream.
+    pub fn position(&self) -> Position {
+        self.buf.pos
+    }
+
+    /// Returns warnings recorded so far.
+    pub fn warnings(&self) -> &[ReaderWarning] {
+        &self.warnings
+    }
+
+    fn warn<Cause: Into<Cow<'static, str>>>(&mut self, pos: Position, cause: Cause) {
+        self.flush_replaced();
+        self.record(ReaderWarning { cause: cause.into(), pos: pos });
+    }
+
+    fn record(&mut self, warning: ReaderWarning) {
+        if let Some(ref mut callback) = self.on_warning { callback(&warning); }
+        self.warnings.push(warning);
+    }
+
+    /// Moves warnings from `Options::lossy_utf8` to `warnings`.
+    fn flush_replaced(&mut self) {
+        let replaced: Vec<_> = self.replaced.borrow_mut().drain(..).collect();
+        for warning in replaced { self.record(warning); }
+    }
+
+    /// Fills the position of an error if it is not yet known.
+    /// This also turns an I/O error caused by `Options::max_input_bytes` into a proper error.
+    ///
+    /// As this is called at the end of every parse, pending warnings are also recorded here.
+    fn locate<T>(&mut self, result: ReaderResult<T>) -> ReaderResult<T> {
+        self.flush_replaced();
+        result.map_err(|mut err| {
+            if err.kind == ErrorKind::Io && self.buf.exceeded {
+                err.kind = ErrorKind::LimitExceeded(Limit::InputBytes);
+                err.cause = format!("exceeded the maximum input size of {} bytes",
+                                    self.options.max_input_bytes).into();
+                err.ioerr = None;
+            }
+            // an invalid data is reported as such by the decoder
+            let invalid_data = match err.ioerr {
+                Some(ref ioerr) if ioerr.kind() == io::ErrorKind::InvalidData =>
+                    Some(ioerr.to_string()),
+                _ => None,
+            };
+            if let Some(cause) = invalid_data {
+                err.kind = ErrorKind::Syntax;
+                err.cause = cause.into();
+                err.ioerr = None;
+            }
+            if err.pos.is_none() { err.pos = Some(self.buf.pos); }
+            err
+        })
+    }
+
+    /// Detects the encoding and skips the byte order mark if any.
+    /// This should be called before reading anything, and does nothing after the first call.
+    fn prologue(&mut self) -> ReaderResult<()> {
+        if self.started { return Ok(()); }
+        self.started = true;
+
+        // read the first bytes directly so that they do not change the position,
+        // and put them back to the stream after the byte order mark is stripped.
+        // they are still charged to `Options::max_input_bytes` as they are read again
+        // through `Tracker`, or as the offset of the byte order mark.
+        let mut head = Vec::new();
+        let limit = cmp::min(4, self.options.max_input_bytes) as u64;
+        try!(Read::by_ref(&mut self.buf.inner).take(limit).read_to_end(&mut head));
+        let detected = util::encoding::detect(&head);
+        let bom = match detected {
+            Detected::Utf8(bom) | Detected::Other(_, bom) => bom,
+            Detected::Unsupported(_) => 0,
+        };
+        let inner = mem::replace(&mut self.buf.inner, Box::new(io::empty()));
+        let inner = io::Cursor::new(head[bom..].to_vec()).chain(inner);
+
+        match detected {
+            Detected::Utf8(bom) => {
+                self.buf.pos.offset = bom;
+                if self.options.lossy_utf8 {
+                    self.buf.inner = Box::new(LossyUtf8::new(Box::new(inner), bom,
+                                                             self.replaced.clone()));
+                    return Ok(());
+                }
+                self.buf.inner = Box::new(inner);
+                match str::from_utf8(&head[bom..]) {
+                    Err(ref e) if e.valid_up_to() == 0 && e.error_len().is_some() => {
+                        reader_err("the input is not encoded in UTF-8")
+                    }
+                    _ => Ok(()),
+                }
+            }
+            Detected::Other(encoding, _) => {
+                if !self.options.detect_encoding {
+                    self.buf.inner = Box::new(inner);
+                    return reader_err(format!("the input seems to be encoded in {}, \
+                                               which requires `Options::detect_encoding`",
+                                              encoding));
+                }
+                self.buf.inner = Box::new(Decoder::new(inner, encoding));
+                Ok(())
+            }
+            Detected::Unsupported(name) => {
+                self.buf.inner = Box::new(inner);
+                reader_err(format!("the input is encoded in {}, which is not supported", name))
+            }
+        }
+    }
+
+    fn document_then_eof(&mut self) -> ReaderResult<repr::Atom<'static>> {
+        try!(self.prologue());
         let ret = try!(self.document());
         try!(self.skip_ws());
+        if self.options.strict_json {
+            if let Some(b':') = try!(self.peek()) {
+                return reader_err("braceless documents are not allowed in JSON");
+            }
+        }
         try!(self.eof());
         Ok(ret)
     }
 
-    pub fn parse_value(mut self) -> ReaderResult<repr::Atom<'static>> {
+    fn value_then_eof(&mut self) -> ReaderResult<repr::Atom<'static>> {
+        try!(self.prologue());
         try!(self.skip_ws());
         let ret = try!(self.value());
         try!(self.skip_ws());
@@ -199,6 +891,195 @@ impl<'a> Reader<'a> {
         Ok(ret)
     }
 
+    /// Parses the next element of the top-level array. See `Reader::elements`.
+    fn next_element_opt(&mut self, state: &mut StreamState)
+            -> ReaderResult<Option<repr::Atom<'static>>> {
+        let count = match *state {
+            StreamState::Start => {
+                try!(self.prologue());
+                try!(self.skip_ws());
+                if try!(self.peek()) != Some(b'[') {
+                    return reader_err("expected `[`");
+                }
+                try!(self.enter());
+                self.buf.consume(1);
+                try!(self.skip_ws());
+                *state = StreamState::Items { braced: true, count: 0 };
+                0
+            }
+            StreamState::Items { count, .. } => count,
+            StreamState::Done => { return Ok(None); }
+        };
+
+        self.nodes = 0;
+        match try!(self.array_item_opt(count)) {
+            Some(value) => {
+                *state = StreamState::Items { braced: true, count: count + 1 };
+                Ok(Some(value))
+            }
+            None => {
+                if try!(self.peek()) != Some(b']') {
+                    return reader_err("expected `]`");
+                }
+                self.buf.consume(1);
+                self.leave();
+                try!(self.skip_ws());
+                try!(self.eof());
+                *state = StreamState::Done;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Parses the next member of the top-level object. See `Reader::members`.
+    fn next_member_opt(&mut self, state: &mut StreamState)
+            -> ReaderResult<Option<(repr::Key<'static>, repr::Atom<'static>)>> {
+        let (braced, count) = match *state {
+            StreamState::Start => {
+                try!(self.prologue());
+                try!(self.skip_ws());
+                let braced = try!(self.peek()) == Some(b'{');
+                if !braced && self.options.strict_json {
+                    return reader_err("expected `{`");
+                }
+                if self.options.tags && try!(self.peek()) == Some(b'@') {
+                    return reader_err("tags are not allowed on the document");
+                }
+                try!(self.enter());
+                if braced {
+                    self.buf.consume(1);
+                    try!(self.skip_ws());
+                }
+                *state = StreamState::Items { braced: braced, count: 0 };
+                (braced, 0)
+            }
+            StreamState::Items { braced, count } => (braced, count),
+            StreamState::Done => { return Ok(None); }
+        };
+
+        self.nodes = 0;
+        match try!(self.object_item_opt(count)) {
+            Some((_, mut path, value)) => {
+                *state = StreamState::Items { braced: braced, count: count + 1 };
+                let mut value = value;
+                while path.len() > 1 {
+                    let key = path.pop().unwrap();
+                    value = repr::Object(vec![(key, value)].into_iter().collect());
+                }
+                Ok(Some((path.pop().unwrap(), value)))
+            }
+            None => {
+                if braced {
+                    if try!(self.peek()) != Some(b'}') {
+                        return reader_err("expected `}`");
+                    }
+                    self.buf.consume(1);
+                }
+                self.leave();
+                try!(self.skip_ws());
+                try!(self.eof());
+                *state = StreamState::Done;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Parses the next document in the stream if any. See `Reader::documents` for the rules.
+    fn next_document_opt(&mut self) -> ReaderResult<Option<repr::Atom<'static>>> {
+        try!(self.prologue());
+        loop {
+            try!(self.skip_ws());
+            match try!(self.peek()) {
+                Some(0x1e) => { self.buf.consume(1); }
+                Some(_) => { break; }
+                None => { return Ok(None); }
+            }
+        }
+
+        self.nodes = 0;
+        let braceless = match try!(self.peek()) {
+            Some(b'{') | Some(b'[') => false,
+            _ => !self.options.strict_json,
+        };
+        let ret = try!(self.document());
+        if braceless {
+            match ret {
+                repr::Object(ref items) if items.is_empty() => {
+                    return reader_err("expected document");
+                }
+                _ => {}
+            }
+            match try!(self.peek()) {
+                Some(0x1e) | Some(b'{') | Some(b'[') | None => {}
+                _ => { return reader_err("expected end of document"); }
+            }
+        }
+        Ok(Some(ret))
+    }
+
+    /// Calls `f` for a nested object or array between `enter` and `leave`.
+    /// `leave` is called even when `f` fails, so that the nesting depth stays balanced.
+    fn nested<T, F>(&mut self, f: F) -> ReaderResult<T>
+        where F: FnOnce(&mut Reader<'a>) -> ReaderResult<T>
+    {
+        try!(self.enter());
+        let ret = f(self);
+        self.leave();
+        ret
+    }
+
+    /// Forgets every nested object or array entered so far, after a streaming iterator
+    /// has failed in the middle of the top-level one.
+    fn reset_nesting(&mut self) {
+        self.depth = 0;
+        self.separators.clear();
+    }
+
+    /// Should be called before parsing a nested object or array.
+    fn enter(&mut self) -> ReaderResult<()> {
+        if self.depth >= self.options.max_depth {
+            return limit_err(Limit::Depth, format!("exceeded the maximum nesting depth of {}",
+                                                   self.options.max_depth));
+        }
+        self.depth += 1;
+        self.separators.push(SeparatorKinds::default());
+        Ok(())
+    }
+
+    /// Should be called after parsing a nested object or array.
+    fn leave(&mut self) {
+        self.depth -= 1;
+        self.separators.pop();
+    }
+
+    /// Notes a `value-separator` at `pos` in the current object or array,
+    /// and warns when both `,` and a newline have been used as separators.
+    fn note_separator(&mut self, pos: Position, comma: bool) {
+        if !self.options.lints { return; }
+        let mixed = match self.separators.last_mut() {
+            Some(kinds) => {
+                if comma { kinds.comma = true; } else { kinds.newline = true; }
+                let mixed = kinds.comma && kinds.newline && !kinds.warned;
+                if mixed { kinds.warned = true; }
+                mixed
+            }
+            None => false,
+        };
+        if mixed {
+            self.warn(pos, "`,` and newlines are mixed as separators in the same object or array");
+        }
+    }
+
+    /// Should be called for every parsed value.
+    fn count_node(&mut self) -> ReaderResult<()> {
+        self.nodes += 1;
+        if self.nodes > self.options.max_nodes {
+            return limit_err(Limit::Nodes, format!("exceeded the maximum number of values of {}",
+                                                   self.options.max_nodes));
+        }
+        Ok(())
+    }
+
     fn eof(&mut self) -> ReaderResult<()> {
         let buf = try!(self.buf.fill_buf());
         if !buf.is_empty() {
@@ -264,12 +1145,31 @@ impl<'a> Reader<'a> {
     /// ~~~~
     fn document(&mut self) -> ReaderResult<repr::Atom<'static>> {
         try!(self.skip_ws());
-        match try!(self.peek()) {
-            Some(b'{') => self.object_no_peek().map(repr::Object),
-            Some(b'[') => self.array_no_peek().map(repr::Array),
-            Some(_) => { try!(self.skip_ws()); Ok(repr::Object(try!(self.object_items_opt()))) },
-            _ => reader_err("expected document"),
+        if self.options.strict_json {
+            // `JSON-text = ws value ws` instead
+            return match try!(self.value_opt()) {
+                Some(value) => Ok(value),
+                None => match try!(self.peek()) {
+                    Some(b) if is_id_start_byte(b) =>
+                        reader_err("braceless documents are not allowed in JSON"),
+                    _ => reader_err("expected document"),
+                },
+            };
         }
+        let ret = match try!(self.peek()) {
+            Some(b'{') => repr::Object(try!(self.object_no_peek())),
+            Some(b'[') => repr::Array(try!(self.array_no_peek())),
+            Some(b'@') if self.options.tags => {
+                return reader_err("tags are not allowed on the document");
+            }
+            Some(_) => {
+                try!(self.skip_ws());
+                repr::Object(try!(self.nested(|r| r.object_items_opt())))
+            },
+            _ => { return reader_err("expected document"); }
+        };
+        try!(self.count_node());
+        Ok(ret)
     }
 
     /// Parses `value-separator` if possible, where:
@@ -280,12 +1180,27 @@ impl<'a> Reader<'a> {
     /// newline = *(%x20 / %x09) newline-char
     /// ~~~~
     fn skip_value_separator_opt(&mut self) -> ReaderResult<Option<()>> {
+        let pos = self.buf.pos;
         let newline = try!(self.skip_ws());
         if try!(self.peek()) == Some(b',') {
+            self.note_separator(pos, true);
             self.buf.consume(1);
             try!(self.skip_ws());
         } else {
             if newline.is_none() { return Ok(None); }
+            let closing = match try!(self.peek()) {
+                Some(b']') | Some(b'}') | None => true,
+                _ => false,
+            };
+            if self.options.strict_json {
+                return if closing {
+                    Ok(None)
+                } else {
+                    reader_err("expected `,`, newlines cannot separate values in JSON")
+                };
+            }
+            // a newline before the closing bracket does not separate anything
+            if !closing { self.note_separator(pos, false); }
         }
         Ok(Some(()))
     }
@@ -306,32 +1221,97 @@ impl<'a> Reader<'a> {
     /// sharp = %x23                    ; # sharp
     /// ~~~~
     ///
+    /// With `Options::json5`, `ws` also has the following:
+    ///
+    /// ~~~~ text
+    /// ws =/ %x0B / %x0C / json5-comment
+    /// json5-comment = %x2F.2F *non-newline-char             ; // ...
+    ///               / %x2F.2A <anything until */> %x2A.2F   ; /* ... */
+    /// ~~~~
+    ///
     /// Returns true when `ws` contains at least one `newline`.
     fn skip_ws(&mut self) -> ReaderResult<Option<Newline>> {
+        let strict = self.options.strict_json;
+        let json5 = self.options.json5;
         let mut newline = None;
+        if mem::replace(&mut self.pending_newline, false) {
+            newline = Some(Newline);
+        }
+        // whether we are in the indentation and it contains a tab
+        let mut indent = newline.is_some() || self.buf.pos.column == 1;
+        let mut tab = false;
         loop {
             let mut comment_chars = false;
+            let mut slash = false;
             try!(self.loop_with_buffer(|buf| {
                 for (i, &v) in buf.iter().enumerate() {
                     match v {
-                        0x20 | 0x09 => {}
-                        0x0a | 0x0d => { newline = Some(Newline); }
-                        0x23 => { comment_chars = true; return Some(i + 1); }
+                        0x20 => {}
+                        0x09 => { tab |= indent; }
+                        0x0b | 0x0c if json5 => {}
+                        0x0a | 0x0d => {
+                            newline = Some(Newline);
+                            indent = true;
+                            tab = false;
+                        }
+                        // do not consume `#` in the strict mode so that the error points to it
+                        0x23 => {
+                            comment_chars = true;
+                            return Some(if strict { i } else { i + 1 });
+                        }
+                        0x2f if json5 => {
+                            slash = true;
+                            return Some(i + 1);
+                        }
                         _ => { return Some(i); }
                     }
                 }
                 None
             }));
 
+            if comment_chars && strict {
+                return reader_err("comments are not allowed in JSON");
+            }
             if comment_chars {
                 try!(self.skip_non_newline_chars());
+            } else if slash {
+                indent = false;
+                match try!(util::io::read_byte(&mut self.buf)) {
+                    Some(b'/') => { try!(self.skip_non_newline_chars()); }
+                    Some(b'*') => {
+                        if try!(self.skip_block_comment()) { newline = Some(Newline); }
+                    }
+                    _ => { return reader_err("expected `//` or `/*` after `/`"); }
+                }
             } else {
+                if tab && self.options.lints && try!(self.peek()).is_some() {
+                    let pos = self.buf.pos;
+                    self.warn(pos, "a tab is used for indentation");
+                }
                 break;
             }
         }
         Ok(newline)
     }
 
+    /// Parses and discards the rest of `/* ... */` after `/*`.
+    ///
+    /// Returns true when the comment contains a newline.
+    fn skip_block_comment(&mut self) -> ReaderResult<bool> {
+        let mut star = false;
+        let mut newline = false;
+        let closed = try!(self.loop_with_buffer(|buf| {
+            for (i, &v) in buf.iter().enumerate() {
+                if star && v == b'/' { return Some(i + 1); }
+                star = v == b'*';
+                if v == b'\n' || v == b'\r' { newline = true; }
+            }
+            None
+        }));
+        if !closed { return reader_err("unterminated comment `/* ... */`"); }
+        Ok(newline)
+    }
+
     /// Parses and discards `*non-newline-char` where:
     ///
     /// ~~~~ {.text}
@@ -347,12 +1327,13 @@ impl<'a> Reader<'a> {
         Ok(())
     }
 
-    /// Parses `*non-newline-char`.
+    /// Parses `*non-newline-char` up to `max_len` bytes.
     ///
     /// It may return an invalid UTF-8 sequence.
     /// The caller is responsible for checking for the valid UTF-8 whenever appropriate.
-    fn non_newline_chars(&mut self) -> ReaderResult<Vec<u8>> {
+    fn non_newline_chars(&mut self, max_len: usize) -> ReaderResult<Vec<u8>> {
         let mut bytes = Vec::new();
+        let mut too_long = false;
         try!(self.loop_with_buffer(|buf| {
             let mut ret = None;
             for (i, &v) in buf.iter().enumerate() {
@@ -362,11 +1343,35 @@ impl<'a> Reader<'a> {
                 }
             }
             bytes.extend(buf[..ret.unwrap_or(buf.len())].iter().map(|&b| b));
+            if bytes.len() > max_len { too_long = true; return Some(0); }
             ret
         }));
+        if too_long {
+            return limit_err(Limit::StringLength, format!("exceeded the maximum string length \
+                                                           of {} bytes", max_len));
+        }
         Ok(bytes)
     }
 
+    /// Parses `newline-char` if possible, where `%x0D %x0A` is also treated as one newline.
+    ///
+    /// Returns the newline parsed.
+    fn newline_char_opt(&mut self) -> ReaderResult<Option<&'static str>> {
+        match try!(self.peek()) {
+            Some(b'\n') => { self.buf.consume(1); Ok(Some("\n")) }
+            Some(b'\r') => {
+                self.buf.consume(1);
+                if try!(self.peek()) == Some(b'\n') {
+                    self.buf.consume(1);
+                    Ok(Some("\r\n"))
+                } else {
+                    Ok(Some("\r"))
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
     /// Given every preceding whitespace skipped, parses `value`.
     fn value(&mut self) -> ReaderResult<repr::Atom<'static>> {
         match try!(self.value_opt()) {
@@ -386,7 +1391,13 @@ impl<'a> Reader<'a> {
     /// true  = %x74.72.75.65           ; true
     /// ~~~~
     fn value_opt(&mut self) -> ReaderResult<Option<repr::Atom<'static>>> {
-        match try!(self.peek()) {
+        let ret = match try!(self.peek()) {
+            Some(b'@') if self.options.tags => self.tagged_value_no_peek().map(Some),
+            // `-` may start a number, which is checked by `number_no_peek`
+            Some(b) if self.options.bare_values && b != b'-' && is_id_start_byte(b) => {
+                let s = try!(self.bare_string_no_peek()).into_owned();
+                Ok(Some(self.bare_value(s)))
+            },
             Some(b'f') => match try!(self.fixed_token_opt(b"false")) {
                 Some(()) => Ok(Some(repr::False)),
                 None => reader_err("expected false"),
@@ -399,17 +1410,80 @@ impl<'a> Reader<'a> {
                 Some(()) => Ok(Some(repr::True)),
                 None => reader_err("expected true"),
             },
+            Some(b'N') if self.options.nan_infinity => self.nan().map(Some),
+            Some(b'I') if self.options.nan_infinity => self.infinity(f64::INFINITY).map(Some),
+            Some(b @ b'+') | Some(b @ b'.') if self.options.json5 => {
+                self.number_no_peek(b).map(Some)
+            },
+            Some(b'+') if self.options.nan_infinity => {
+                self.buf.consume(1);
+                self.infinity(f64::INFINITY).map(Some)
+            },
             Some(b'{') => self.object_no_peek().map(|v| Some(repr::Object(v))),
             Some(b'[') => self.array_no_peek().map(|v| Some(repr::Array(v))),
             Some(b @ b'-') | Some(b @ b'0'...b'9') => self.number_no_peek(b).map(Some),
+            Some(b'\'') | Some(b'|') if self.options.strict_json => {
+                reader_err("only double-quoted strings are allowed in JSON")
+            },
             Some(quote @ b'"') | Some(quote @ b'\'') =>
                 self.string_no_peek(quote).map(|s| Some(repr::OwnedString(s.to_string()))),
-            Some(b'|') => {
-                let frags = try!(self.verbatim_string_no_peek());
-                let frags_: Vec<&str> = frags.iter().map(|s| &s[..]).collect(); // XXX
-                Ok(Some(repr::OwnedString(frags_.connect("\n"))))
-            },
+            Some(b'|') => self.verbatim_string_no_peek().map(|s| Some(repr::OwnedString(s))),
             _ => Ok(None),
+        };
+        if let Ok(Some(_)) = ret { try!(self.count_node()); }
+        ret
+    }
+
+    /// Given a known lookahead, parses `tagged-value` which is an extension enabled by
+    /// `Options::tags`, where:
+    ///
+    /// ~~~~ text
+    /// tagged-value = %x40 bare-string ws value    ; @ commercial at
+    /// ~~~~
+    fn tagged_value_no_peek(&mut self) -> ReaderResult<repr::Atom<'static>> {
+        assert_eq!(self.peek().unwrap(), Some(b'@'));
+
+        self.buf.consume(1);
+        let tag = match try!(self.peek()) {
+            Some(b) if is_id_start_byte(b) => try!(self.bare_string_no_peek()).into_owned(),
+            _ => { return reader_err("expected a tag name after `@`"); }
+        };
+        // tags can be nested as in `@a @b 1`, so they count towards the nesting depth
+        let (pos, value) = try!(self.nested(|r| {
+            try!(r.skip_ws());
+            let pos = r.buf.pos;
+            match try!(r.value_opt()) {
+                Some(value) => Ok((pos, value)),
+                None => reader_err(format!("expected a value after `@{}`", tag)),
+            }
+        }));
+        let value = match self.tags.get_mut(&tag) {
+            Some(converter) => match converter(value) {
+                Ok(value) => value,
+                Err(cause) => {
+                    return reader_err_at(pos, format!("invalid value for `@{}`: {}", tag, cause));
+                }
+            },
+            None => value,
+        };
+        Ok(repr::Tagged(tag, Box::new(value)))
+    }
+
+    /// Parses `NaN`, which is an extension enabled by `Options::nan_infinity`.
+    /// A sign may precede it with `Options::json5`.
+    fn nan(&mut self) -> ReaderResult<repr::Atom<'static>> {
+        match try!(self.fixed_token_opt(b"NaN")) {
+            Some(()) => Ok(repr::F64(f64::NAN)),
+            None => reader_err("expected NaN"),
+        }
+    }
+
+    /// Parses `Infinity` after an optional sign, which is an extension enabled by
+    /// `Options::nan_infinity`.
+    fn infinity(&mut self, value: f64) -> ReaderResult<repr::Atom<'static>> {
+        match try!(self.fixed_token_opt(b"Infinity")) {
+            Some(()) => Ok(repr::F64(value)),
+            None => reader_err("expected Infinity"),
         }
     }
 
@@ -424,14 +1498,16 @@ impl<'a> Reader<'a> {
     fn object_no_peek(&mut self) -> ReaderResult<repr::AtomObject<'static>> {
         assert_eq!(self.peek().unwrap(), Some(b'{'));
 
-        self.buf.consume(1);
-        try!(self.skip_ws());
-        let items = try!(self.object_items_opt());
-        if try!(self.peek()) != Some(b'}') {
-            return reader_err("expected `}`");
-        }
-        self.buf.consume(1);
-        Ok(items)
+        self.nested(|r| {
+            r.buf.consume(1);
+            try!(r.skip_ws());
+            let items = try!(r.object_items_opt());
+            if try!(r.peek()) != Some(b'}') {
+                return reader_err("expected `}`");
+            }
+            r.buf.consume(1);
+            Ok(items)
+        })
     }
 
     /// Parses `[ object-items ]` where:
@@ -444,21 +1520,143 @@ impl<'a> Reader<'a> {
     /// newline = *(%x20 / %x09) newline-char
     /// ~~~~
     fn object_items_opt(&mut self) -> ReaderResult<repr::AtomObject<'static>> {
-        let mut items = BTreeMap::new();
-        let (firstkey, firstvalue) = match try!(self.member_opt()) {
+        let mut items = repr::AtomObject::new();
+        let mut seen = BTreeMap::new();
+        let mut count = 0;
+        while let Some((pos, path, value)) = try!(self.object_item_opt(count)) {
+            count += 1;
+            try!(self.insert_member(&mut items, &mut seen, pos, path, value));
+        }
+        Ok(items)
+    }
+
+    /// Parses the next `member` of `object-items` if possible, which is preceded by
+    /// `value-separator` unless it is the first one. `count` is the number of members so far.
+    ///
+    /// Returns the member along with its starting position.
+    fn object_item_opt(&mut self, count: usize)
+            -> ReaderResult<Option<(Position, Vec<Key<'static>>, repr::Atom<'static>)>> {
+        if count > 0 && try!(self.skip_value_separator_opt()).is_none() { return Ok(None); }
+        let pos = self.buf.pos;
+        let (path, value) = match try!(self.member_opt()) {
             Some(member) => member,
-            None => { return Ok(items); }
+            None if count > 0 && self.options.strict_json => {
+                return reader_err("trailing separators are not allowed in JSON");
+            }
+            None => { return Ok(None); }
         };
-        items.insert(firstkey, firstvalue);
-        loop {
-            if try!(self.skip_value_separator_opt()).is_none() { break; }
-            let (key, value) = match try!(self.member_opt()) {
-                Some(member) => member,
-                None => { break; }
-            };
+        if count >= self.options.max_object_members {
+            return limit_err(Limit::ObjectMembers,
+                             format!("exceeded the maximum number of object members of {}",
+                                     self.options.max_object_members));
+        }
+        Ok(Some((pos, path, value)))
+    }
+
+    /// Inserts a member starting at `pos` into `items` according to the duplicate key policy.
+    ///
+    /// `path` has more than one key only with `Options::dotted_keys`, in which case
+    /// intermediate objects are created or merged as needed.
+    ///
+    /// `seen` maps every path so far to its first position and the number of occurrences,
+    /// where an object created by dotted keys has no occurrences. Keys inside an object value
+    /// are only added when a dotted key repeats them. It is not maintained for
+    /// `DuplicateKeys::LastWins` without dotted keys and lints, which needs no bookkeeping.
+    fn insert_member(&mut self, items: &mut repr::AtomObject<'static>,
+                     seen: &mut BTreeMap<Vec<Key<'static>>, (Position, usize)>, pos: Position,
+                     mut path: Vec<Key<'static>>, value: repr::Atom<'static>) -> ReaderResult<()> {
+        let policy = self.options.duplicate_keys;
+        let dotted = self.options.dotted_keys;
+        let key = path.pop().unwrap();
+        if policy == DuplicateKeys::LastWins && !dotted && !self.options.lints {
             items.insert(key, value);
+            return Ok(());
         }
-        Ok(items)
+
+        if dotted && policy == DuplicateKeys::Collect && !path.is_empty() {
+            return reader_err_at(pos, "dotted keys cannot be used with `DuplicateKeys::Collect`");
+        }
+
+        let mut items = items;
+        for i in 0..path.len() {
+            let prefix = path[..i + 1].to_vec();
+            match items.get(&path[i]) {
+                Some(&repr::Object(_)) => {} // shares a prefix with earlier members
+                Some(_) => {
+                    // keys inside an object value are not in `seen`
+                    let first = seen.get(&prefix).map_or(String::new(), |&(first, _)| {
+                        format!(" (first defined at {})", first)
+                    });
+                    return reader_err_at(pos, format!("`{}` is already defined as a non-object \
+                                                       value{}", join_path(&prefix), first));
+                }
+                None => {
+                    items.insert(path[i].clone(), repr::Object(repr::AtomObject::new()));
+                    seen.insert(prefix, (pos, 0));
+                }
+            }
+            items = match *items.get_mut(&path[i]).unwrap() {
+                repr::Object(ref mut items) => items,
+                _ => unreachable!(),
+            };
+        }
+        path.push(key.clone());
+
+        let first = match seen.get_mut(&path) {
+            Some(&mut (first, 0)) => {
+                return reader_err_at(pos, format!("`{}` is already defined as an object by \
+                                                   dotted keys (first defined at {})",
+                                                  join_path(&path), first));
+            }
+            Some(&mut (first, ref mut count)) => { *count += 1; Some(first) }
+            None => None,
+        };
+        let first = match first {
+            Some(first) => first,
+            None if items.contains_key(&key) => {
+                // the key comes from an object value, so count it from the enclosing member
+                let first = (1..path.len()).rev().filter_map(|i| seen.get(&path[..i]))
+                    .map(|&(first, _)| first).next().unwrap();
+                seen.insert(path.clone(), (first, 2));
+                first
+            }
+            None => {
+                seen.insert(path, (pos, 1));
+                let value = match policy {
+                    DuplicateKeys::Collect => repr::Array(vec![value]),
+                    _ => value,
+                };
+                items.insert(key, value);
+                return Ok(());
+            }
+        };
+        let key = join_path(&path);
+        match policy {
+            DuplicateKeys::Error => {
+                return reader_err_at(pos, format!("duplicate key `{}` (first defined at {})",
+                                                  key, first));
+            }
+            DuplicateKeys::Warn => {
+                self.warn(pos, format!("duplicate key `{}` overrides the value at {}",
+                                       key, first));
+                items.insert(path.pop().unwrap(), value);
+            }
+            DuplicateKeys::FirstWins => {}
+            DuplicateKeys::LastWins => {
+                if self.options.lints {
+                    self.warn(pos, format!("duplicate key `{}` overrides the value at {}",
+                                           key, first));
+                }
+                items.insert(path.pop().unwrap(), value);
+            }
+            DuplicateKeys::Collect => {
+                let key = path.pop().unwrap();
+                if let Some(&mut repr::Array(ref mut values)) = items.get_mut(&key) {
+                    values.push(value);
+                }
+            }
+        }
+        Ok(())
     }
 
     /// Parses `member` if possible, where:
@@ -466,20 +1664,65 @@ impl<'a> Reader<'a> {
     /// ~~~~ {.text}
     /// member = name name-separator value
     /// ~~~~
-    fn member_opt(&mut self) -> ReaderResult<Option<(repr::Key<'static>,
+    ///
+    /// Returns the path of keys, which has more than one key only with `Options::dotted_keys`.
+    fn member_opt(&mut self) -> ReaderResult<Option<(Vec<Key<'static>>,
                                                      repr::Atom<'static>)>> {
-        let name = match try!(self.name_opt()) {
-            Some(name) => name,
+        let path = match try!(self.key_path_opt()) {
+            Some(path) => path,
             None => { return Ok(None); }
         };
         try!(self.skip_ws());
         match try!(self.peek()) {
+            Some(b'=') if self.options.strict_json => {
+                return reader_err("expected `:`, `=` is not allowed in JSON");
+            }
             Some(b':') | Some(b'=') => { self.buf.consume(1); }
             _ => { return reader_err("expected `:` or `=`"); }
         }
         try!(self.skip_ws());
         let value = try!(self.value());
-        Ok(Some((Key::new(name), value)))
+        Ok(Some((path, value)))
+    }
+
+    /// Parses `name` if possible. With `Options::dotted_keys`, parses the following instead:
+    ///
+    /// ~~~~ text
+    /// dotted-key = name *( %x2E name )
+    /// ~~~~
+    ///
+    /// where `.` inside `bare-string` also separates names, but not inside `string`.
+    fn key_path_opt(&mut self) -> ReaderResult<Option<Vec<Key<'static>>>> {
+        let mut path = Vec::new();
+        loop {
+            let quoted = match try!(self.peek()) {
+                Some(b'"') | Some(b'\'') => true,
+                _ => false,
+            };
+            let name = match try!(self.name_opt()) {
+                Some(name) => name,
+                None if path.is_empty() => { return Ok(None); }
+                None => { return reader_err("expected a key after `.`"); }
+            };
+            if !self.options.dotted_keys { return Ok(Some(vec![Key::new(name)])); }
+
+            let continues = if quoted {
+                path.push(Key::new(name));
+                try!(self.peek()) == Some(b'.')
+            } else {
+                let mut names: Vec<&str> = name.split('.').collect();
+                // `a."b"` is read as a bare string `a.` followed by a string
+                let continues = names.len() > 1 && names.last() == Some(&"");
+                if continues { names.pop(); }
+                if names.iter().any(|name| name.is_empty()) {
+                    return reader_err("a dotted key cannot have an empty name");
+                }
+                path.extend(names.into_iter().map(|name| Key::new(name.to_string())));
+                continues
+            };
+            if !continues { return Ok(Some(path)); }
+            if quoted { self.buf.consume(1); }
+        }
     }
 
     /// Parses `name` if possible, where:
@@ -496,9 +1739,14 @@ impl<'a> Reader<'a> {
     /// ~~~~
     fn name_opt(&mut self) -> ReaderResult<Option<Cow<'static, str>>> {
         match try!(self.peek()) {
+            Some(b'\'') if self.options.strict_json =>
+                reader_err("only double-quoted strings are allowed in JSON"),
+            Some(b) if self.options.strict_json && is_id_start_byte(b) =>
+                reader_err("bare keys are not allowed in JSON"),
             Some(quote @ b'"') | Some(quote @ b'\'') =>
                 self.string_no_peek(quote).map(|s| Some(s.into())),
             Some(b) if is_id_start_byte(b) => self.bare_string_no_peek().map(Some),
+            Some(b'\\') if self.options.json5 => self.bare_string_no_peek().map(Some),
             _ => Ok(None),
         }
     }
@@ -514,14 +1762,16 @@ impl<'a> Reader<'a> {
     fn array_no_peek(&mut self) -> ReaderResult<repr::AtomArray<'static>> {
         assert_eq!(self.peek().unwrap(), Some(b'['));
 
-        self.buf.consume(1);
-        try!(self.skip_ws());
-        let elements = try!(self.array_items_opt());
-        if try!(self.peek()) != Some(b']') {
-            return reader_err("expected `]`");
-        }
-        self.buf.consume(1);
-        Ok(elements)
+        self.nested(|r| {
+            r.buf.consume(1);
+            try!(r.skip_ws());
+            let elements = try!(r.array_items_opt());
+            if try!(r.peek()) != Some(b']') {
+                return reader_err("expected `]`");
+            }
+            r.buf.consume(1);
+            Ok(elements)
+        })
     }
 
     /// Parses `[ array-items ]` where:
@@ -531,35 +1781,59 @@ impl<'a> Reader<'a> {
     /// ~~~~
     fn array_items_opt(&mut self) -> ReaderResult<repr::AtomArray<'static>> {
         let mut elements = Vec::new();
-        let first = match try!(self.value_opt()) {
-            Some(first) => first,
-            None => { return Ok(elements); }
-        };
-        elements.push(first);
-        loop {
-            if try!(self.skip_value_separator_opt()).is_none() { break; }
-            let value = match try!(self.value_opt()) {
-                Some(value) => value,
-                None => { break; }
-            };
+        while let Some(value) = try!(self.array_item_opt(elements.len())) {
             elements.push(value);
         }
         Ok(elements)
     }
 
+    /// Parses the next `value` of `array-items` if possible, which is preceded by
+    /// `value-separator` unless it is the first one. `count` is the number of values so far.
+    fn array_item_opt(&mut self, count: usize) -> ReaderResult<Option<repr::Atom<'static>>> {
+        if count > 0 && try!(self.skip_value_separator_opt()).is_none() { return Ok(None); }
+        let value = match try!(self.value_opt()) {
+            Some(value) => value,
+            None if count > 0 && self.options.strict_json => {
+                return reader_err("trailing separators are not allowed in JSON");
+            }
+            None => { return Ok(None); }
+        };
+        if count >= self.options.max_array_len {
+            return limit_err(Limit::ArrayLength, format!("exceeded the maximum array length of {}",
+                                                         self.options.max_array_len));
+        }
+        Ok(Some(value))
+    }
+
     /// Parses and pushes `*DIGITS` into `bytes`.
+    ///
+    /// `bytes` is the whole number literal so far and is subject to `Options::max_number_len`.
     fn digits_opt(&mut self, bytes: &mut Vec<u8>) -> ReaderResult<()> {
+        self.radix_digits_opt(bytes, 10)
+    }
+
+    /// Parses `*DIGIT` in given radix. `_` is also accepted with `Options::extended_numbers`,
+    /// and should be checked with `check_digit_separators`.
+    fn radix_digits_opt(&mut self, bytes: &mut Vec<u8>, radix: u32) -> ReaderResult<()> {
+        let max_len = self.options.max_number_len;
+        let separators = self.options.extended_numbers;
+        let mut too_long = false;
         try!(self.loop_with_buffer(|buf| {
             let mut ret = None;
             for (i, &v) in buf.iter().enumerate() {
-                if v < b'0' || b'9' < v {
+                if !(v as char).is_digit(radix) && !(separators && v == b'_') {
                     ret = Some(i);
                     break;
                 }
             }
             bytes.extend(buf[..ret.unwrap_or(buf.len())].iter().map(|&b| b));
+            if bytes.len() > max_len { too_long = true; return Some(0); }
             ret
         }));
+        if too_long || bytes.len() > max_len {
+            return limit_err(Limit::NumberLength, format!("exceeded the maximum number length \
+                                                           of {} bytes", max_len));
+        }
         Ok(())
     }
 
@@ -577,25 +1851,55 @@ impl<'a> Reader<'a> {
     /// plus = %x2B                     ; +
     /// zero = %x30                     ; 0
     /// ~~~~
+    ///
+    /// With `Options::json5`, a number can start with `plus` or `decimal-point`,
+    /// and `int` can be followed by a decimal point without digits.
+    /// With `Options::bare_values`, `minus` not followed by a digit starts a bare string.
     fn number_no_peek(&mut self, initial: u8) -> ReaderResult<repr::Atom<'static>> {
         assert_eq!(self.peek().unwrap(), Some(initial));
+        let start = self.buf.pos;
 
-        self.buf.consume(1);
+        // `.5` is read as `0.5`, leaving the decimal point for `frac`
+        let mut bytes = vec![b'0'];
+        let mut leading_point = initial == b'.';
+        if !leading_point {
+            self.buf.consume(1);
 
-        // special case. both JSON and CSON does not allow a zero-padded non-zero number.
-        let next = try!(self.peek());
-        if initial == b'0' && next != Some(b'.') && next != Some(b'e') && next != Some(b'E') {
-            // as long as it is not followed by `frac` and `exp`, we are free to shortcut
-            return Ok(repr::I64(0));
-        }
+            // special case. both JSON and CSON does not allow a zero-padded non-zero number.
+            let next = try!(self.peek());
+            if initial == b'0' && self.radix_allowed(next) {
+                return self.radix_number_no_peek(bytes);
+            }
+            if initial == b'0' && next != Some(b'.') && next != Some(b'e') && next != Some(b'E') {
+                // as long as it is not followed by `frac` and `exp`, we are free to shortcut
+                return Ok(repr::I64(0));
+            }
 
-        let mut bytes = vec![initial];
+            bytes = vec![initial];
+        }
 
         // we need to ensure if this parse would end up with at least one number
-        if initial == b'-' {
+        if initial == b'-' || initial == b'+' {
+            let infinity = if initial == b'-' { f64::NEG_INFINITY } else { f64::INFINITY };
             match try!(self.peek()) {
                 Some(b @ b'0'...b'9') => { bytes.push(b); self.buf.consume(1); }
-                _ => { return reader_err("expected a number, got `-`"); }
+                Some(b'.') if self.options.json5 => { bytes.push(b'0'); leading_point = true; }
+                _ if initial == b'-' && self.options.bare_values => {
+                    let s = try!(self.bare_string_rest("-".to_string()));
+                    return Ok(self.bare_value(s));
+                }
+                Some(b'N') if self.options.nan_infinity && self.options.json5 => {
+                    return self.nan();
+                }
+                Some(b'I') if self.options.nan_infinity => {
+                    return self.infinity(infinity);
+                }
+                _ => { return reader_err(format!("expected a number, got `{}`",
+                                                 initial as char)); }
+            }
+            let next = try!(self.peek());
+            if bytes[1] == b'0' && self.radix_allowed(next) {
+                return self.radix_number_no_peek(bytes);
             }
         }
 
@@ -606,13 +1910,17 @@ impl<'a> Reader<'a> {
         let mut try_integral = true;
         match try!(self.peek()) {
             Some(b'.') => {
-                bytes.push(b'.');
                 self.buf.consume(1);
                 match try!(self.peek()) {
-                    Some(b @ b'0'...b'9') => { bytes.push(b); self.buf.consume(1); }
+                    Some(b @ b'0'...b'9') => {
+                        bytes.push(b'.');
+                        bytes.push(b);
+                        self.buf.consume(1);
+                        try!(self.digits_opt(&mut bytes));
+                    }
+                    _ if self.options.json5 && !leading_point => {}
                     _ => { return reader_err("a number cannot have a trailing decimal point"); }
                 }
-                try!(self.digits_opt(&mut bytes));
                 try_integral = false;
             }
             _ => {}
@@ -637,15 +1945,81 @@ impl<'a> Reader<'a> {
             _ => {}
         }
 
+        if self.options.extended_numbers {
+            try!(check_digit_separators(&bytes, 10));
+            bytes.retain(|&b| b != b'_');
+        }
         let s = str::from_utf8(&bytes).unwrap();
         if try_integral {
-            // try to return as `I64` if possible
-            match s.parse::<i64>() {
-                Ok(v) if (-1<<53) < v && v < (1<<53) => { return Ok(repr::I64(v)); }
-                _ => {}
-            }
+            // keep the integer exact as `I64`, or `U64` if it only fits there
+            if let Ok(v) = s.parse::<i64>() { return Ok(repr::I64(v)); }
+            if let Ok(v) = s.parse::<u64>() { return Ok(repr::U64(v)); }
+        }
+        let v = s.parse::<f64>().unwrap();
+        if self.options.lints && !is_exact_f64(s, v) {
+            self.warn(start, format!("the number `{}` is rounded to `{:?}`", s, v));
+        }
+        Ok(repr::F64(v))
+    }
+
+    /// Returns true if `prefix` after `0` starts `radix-integer`. JSON5 only has the
+    /// hexadecimal one, so `Options::json5` alone accepts `0x` but not `0o` or `0b`.
+    fn radix_allowed(&self, prefix: Option<u8>) -> bool {
+        match radix_of(prefix) {
+            Some(16) => self.options.extended_numbers || self.options.json5,
+            Some(_) => self.options.extended_numbers,
+            None => false,
+        }
+    }
+
+    /// Given a known lookahead, parses the rest of an integer with a radix prefix after `0`,
+    /// which is an extension enabled by `Options::extended_numbers`:
+    ///
+    /// ~~~~ text
+    /// radix-integer = [ minus ] zero ( x 1*HEXDIG / o 1*ODIGIT / b 1*BIT )
+    /// x = %x78 / %x58                 ; x X
+    /// o = %x6F / %x4F                 ; o O
+    /// b = %x62 / %x42                 ; b B
+    /// ODIGIT = %x30-37                ; 0-7
+    /// ~~~~
+    ///
+    /// Digits may be separated by `_`.
+    ///
+    /// `bytes` is the literal so far, which is `0` after an optional sign. The whole literal
+    /// including the sign and the prefix is subject to `Options::max_number_len`.
+    fn radix_number_no_peek(&mut self, mut bytes: Vec<u8>) -> ReaderResult<repr::Atom<'static>> {
+        let negative = bytes[0] == b'-';
+        let prefix = try!(self.peek()).unwrap();
+        let radix = radix_of(Some(prefix)).unwrap();
+        self.buf.consume(1);
+
+        bytes.push(prefix);
+        let start = bytes.len();
+        try!(self.radix_digits_opt(&mut bytes, radix));
+        let mut digits = bytes.split_off(start);
+        if digits.is_empty() {
+            return reader_err(format!("expected digits after `0{}`", prefix as char));
+        }
+        try!(check_digit_separators(&digits, radix));
+        digits.retain(|&b| b != b'_');
+
+        let s = str::from_utf8(&digits).unwrap();
+        let too_large = || {
+            reader_err(format!("the integer `{}0{}{}` does not fit in 64 bits",
+                               if negative { "-" } else { "" }, prefix as char, s))
+        };
+        let v = match u64::from_str_radix(s, radix) {
+            Ok(v) => v,
+            Err(_) => { return too_large(); }
+        };
+        if negative {
+            if v > 1 << 63 { return too_large(); }
+            Ok(repr::I64((v as i64).wrapping_neg()))
+        } else if v > i64::MAX as u64 {
+            Ok(repr::U64(v))
+        } else {
+            Ok(repr::I64(v as i64))
         }
-        Ok(repr::F64(s.parse::<f64>().unwrap()))
     }
 
     /// Given a known lookahead, parses `string` where:
@@ -671,13 +2045,23 @@ impl<'a> Reader<'a> {
     /// squoted-unescaped = %x20-26 / %x28-5B / %x5D-10FFFF
     /// ~~~~
     fn quoted_chars_then_quote(&mut self, quote: u8) -> ReaderResult<Cow<'static, str>> {
+        let max_len = self.options.max_string_len;
+        let strict = self.options.strict_json;
+        let keep_line_endings = self.options.keep_line_endings;
         let mut bytes: Vec<u8> = Vec::new();
+        // true when the last unescaped character was `\r`, so that `\r\n` becomes one `\n`
+        let mut after_cr = false;
         loop {
             let mut escaped_follows = false;
+            let mut too_long = false;
+            let mut control_char = false;
             let keepgoing = try!(self.loop_with_buffer(|buf| {
                 let mut ret = None;
                 for (i, &v) in buf.iter().enumerate() {
-                    if v == b'\\' {
+                    if strict && v < 0x20 {
+                        control_char = true;
+                        return Some(i);
+                    } else if v == b'\\' {
                         escaped_follows = true;
                         ret = Some(i + 1);
                         break;
@@ -687,15 +2071,45 @@ impl<'a> Reader<'a> {
                     }
                 }
                 // `ret`, if set, contains one additional byte which should not be in `bytes`.
-                bytes.extend(buf[..ret.map_or(buf.len(), |i| i-1)].iter().map(|&b| b));
+                let unescaped = &buf[..ret.map_or(buf.len(), |i| i-1)];
+                if keep_line_endings {
+                    bytes.extend(unescaped.iter().map(|&b| b));
+                } else {
+                    for &b in unescaped {
+                        match b {
+                            b'\r' => { bytes.push(b'\n'); }
+                            b'\n' if after_cr => {}
+                            _ => { bytes.push(b); }
+                        }
+                        after_cr = b == b'\r';
+                    }
+                }
+                if bytes.len() > max_len { too_long = true; return Some(0); }
                 ret
             }));
+            if too_long {
+                return limit_err(Limit::StringLength, format!("exceeded the maximum string \
+                                                               length of {} bytes", max_len));
+            }
+            if control_char {
+                return reader_err("control characters should be escaped in JSON strings");
+            }
             if !keepgoing {
                 return reader_err("incomplete string literal");
             }
 
             if escaped_follows {
-                let ch = match try!(self.escaped_minus_escape()) {
+                after_cr = false;
+                let unit = if self.options.extended_escapes || self.options.json5 {
+                    match try!(self.extended_escape()) {
+                        Escaped::Char(ch) => { push_char(&mut bytes, ch); continue; }
+                        Escaped::Unit(unit) => unit,
+                        Escaped::Nothing => { continue; }
+                    }
+                } else {
+                    try!(self.escaped_minus_escape())
+                };
+                let ch = match unit {
                     first @ 0xd800...0xdbff => {
                         // lower surrogate, should be followed by an escaped upper surrogate
                         if try!(self.peek()) != Some(b'\\') {
@@ -719,12 +2133,7 @@ impl<'a> Reader<'a> {
                     ch => ch as u32,
                 };
 
-                // append a converted UTF-8 sequence into `bytes`.
-                // this wouldn't affect the validness of other raw `bytes` as UTF-8 ensures that
-                // no valid sequence can made into invalid one or vice versa.
-                let mut charbuf = [0u8; 4];
-                let charbuflen = util::char::encode_utf8_raw(ch, &mut charbuf).unwrap();
-                bytes.extend(charbuf[..charbuflen].iter().map(|&b| b));
+                push_char(&mut bytes, ch);
             } else {
                 break;
             }
@@ -757,6 +2166,8 @@ impl<'a> Reader<'a> {
     /// The caller is expected to deal with such cases.
     fn escaped_minus_escape(&mut self) -> ReaderResult<u16> {
         match try!(util::io::read_byte(&mut self.buf)) {
+            Some(b'\'') if self.options.strict_json =>
+                reader_err("unknown escape sequence `\\'` in JSON"),
             Some(b'\'') => Ok(0x27),
             Some(b'"') => Ok(0x22),
             Some(b'\\') => Ok(0x5c),
@@ -766,24 +2177,122 @@ impl<'a> Reader<'a> {
             Some(b'n') => Ok(0x0a),
             Some(b'r') => Ok(0x0d),
             Some(b't') => Ok(0x09),
+            Some(b'u') => self.unicode_escape_digits(),
+            Some(b'v') if self.options.json5 => Ok(0x0b),
+            // any other character escapes itself in JSON5, except for digits and newlines
+            Some(ch) if self.options.json5 && ch < 0x80 && !(ch as char).is_digit(10) &&
+                        ch != b'\n' && ch != b'\r' && ch != b'x' => Ok(ch as u16),
+            Some(ch) => reader_err(format!("unknown escape sequence `\\{}`", ch)),
+            None => reader_err("incomplete escape sequence"),
+        }
+    }
+
+    /// Parses `4HEXDIG` after `\u`.
+    fn unicode_escape_digits(&mut self) -> ReaderResult<u16> {
+        let mut read_hex_digit = || {
+            match try!(util::io::read_byte(&mut self.buf)) {
+                Some(b @ b'0'...b'9') => Ok((b - b'0') as u16 + 0),
+                Some(b @ b'a'...b'f') => Ok((b - b'a') as u16 + 10),
+                Some(b @ b'A'...b'F') => Ok((b - b'A') as u16 + 10),
+                Some(_) => reader_err("invalid hexadecimal digits after `\\u`"),
+                None => reader_err("incomplete escape sequence"),
+            }
+        };
+        let a = try!(read_hex_digit());
+        let b = try!(read_hex_digit());
+        let c = try!(read_hex_digit());
+        let d = try!(read_hex_digit());
+        Ok((a << 12) | (b << 8) | (c << 4) | d)
+    }
+
+    /// Parses `escaped` minus `escape` with `Options::extended_escapes`, where:
+    ///
+    /// ~~~~ text
+    /// escaped =/ escape (
+    ///             %x75 %x7B 1*6HEXDIG %x7D /  ; u{X...}    U+X...
+    ///             %x78 2HEXDIG /              ; xHH        U+00HH, up to U+007F (U+00FF in JSON5)
+    ///             %x30 /                      ; 0          U+0000, not followed by DIGIT
+    ///             newline-char *(%x20 / %x09) ; line continuation
+    ///           )
+    /// ~~~~
+    ///
+    /// `Options::json5` alone accepts the subset in JSON5, which is `\xHH`, `\0` and
+    /// a line continuation that only removes the newline.
+    fn extended_escape(&mut self) -> ReaderResult<Escaped> {
+        match try!(self.peek()) {
             Some(b'u') => {
-                let mut read_hex_digit = || {
-                    match try!(util::io::read_byte(&mut self.buf)) {
-                        Some(b @ b'0'...b'9') => Ok((b - b'0') as u16 + 0),
-                        Some(b @ b'a'...b'f') => Ok((b - b'a') as u16 + 10),
-                        Some(b @ b'A'...b'F') => Ok((b - b'A') as u16 + 10),
-                        Some(_) => reader_err("invalid hexadecimal digits after `\\u`"),
-                        None => reader_err("incomplete escape sequence"),
+                self.buf.consume(1);
+                if !self.options.extended_escapes || try!(self.peek()) != Some(b'{') {
+                    return self.unicode_escape_digits().map(Escaped::Unit);
+                }
+                self.buf.consume(1);
+                let mut digits = Vec::new();
+                try!(self.radix_digits_opt(&mut digits, 16));
+                if digits.is_empty() || digits.contains(&b'_') {
+                    return reader_err("expected hexadecimal digits in `\\u{...}`");
+                }
+                if digits.len() > 6 {
+                    return reader_err("`\\u{...}` can have at most 6 hexadecimal digits");
+                }
+                if try!(util::io::read_byte(&mut self.buf)) != Some(b'}') {
+                    return reader_err("expected `}` after `\\u{`");
+                }
+                let ch = u32::from_str_radix(str::from_utf8(&digits).unwrap(), 16).unwrap();
+                match ch {
+                    0xd800...0xdfff => {
+                        reader_err(format!("`\\u{{{:x}}}` is a surrogate, which is not \
+                                            a character", ch))
+                    }
+                    0x110000...0xffffff => {
+                        reader_err(format!("`\\u{{{:x}}}` is out of the Unicode range", ch))
+                    }
+                    ch => Ok(Escaped::Char(ch)),
+                }
+            }
+            Some(b'x') => {
+                self.buf.consume(1);
+                let mut digits = [0u8; 2];
+                match try!(util::io::read_at_least(&mut self.buf, 2, &mut digits)) {
+                    util::io::ReadBytes::Enough(_) => {}
+                    util::io::ReadBytes::NotEnough(_) => {
+                        return reader_err("incomplete escape sequence");
                     }
+                }
+                let ch = str::from_utf8(&digits).ok().and_then(|s| u8::from_str_radix(s, 16).ok());
+                let ch = match ch {
+                    Some(ch) => ch,
+                    None => { return reader_err("invalid hexadecimal digits after `\\x`"); }
                 };
-                let a = try!(read_hex_digit());
-                let b = try!(read_hex_digit());
-                let c = try!(read_hex_digit());
-                let d = try!(read_hex_digit());
-                Ok((a << 12) | (b << 8) | (c << 4) | d)
-            },
-            Some(ch) => reader_err(format!("unknown escape sequence `\\{}`", ch)),
-            None => reader_err("incomplete escape sequence"),
+                if ch > 0x7f && !self.options.json5 {
+                    return reader_err(format!("`\\x{:02x}` is out of the ASCII range, \
+                                               use `\\u{{{:x}}}` instead", ch, ch));
+                }
+                Ok(Escaped::Char(ch as u32))
+            }
+            Some(b'0') => {
+                self.buf.consume(1);
+                match try!(self.peek()) {
+                    Some(b'0'...b'9') => reader_err("`\\0` cannot be followed by a digit"),
+                    _ => Ok(Escaped::Char(0)),
+                }
+            }
+            Some(b'\n') | Some(b'\r') => {
+                try!(self.newline_char_opt());
+                // JSON5 only removes the newline itself
+                if self.options.extended_escapes {
+                    try!(self.loop_with_buffer(|buf| {
+                        buf.iter().position(|&v| v != b' ' && v != b'\t')
+                    }));
+                }
+                Ok(Escaped::Nothing)
+            }
+            Some(b) if b >= 0x80 && self.options.json5 => {
+                match try!(util::io::read_char(&mut self.buf)) {
+                    Some(ch) => Ok(Escaped::Char(ch as u32)),
+                    None => reader_err("invalid UTF-8 sequence in a quoted string"),
+                }
+            }
+            _ => self.escaped_minus_escape().map(Escaped::Unit),
         }
     }
 
@@ -794,21 +2303,50 @@ impl<'a> Reader<'a> {
     /// verbatim-fragment = pipe *verbatim-char
     /// pipe = %x7C                     ; |
     /// ~~~~
-    fn verbatim_string_no_peek(&mut self) -> ReaderResult<Vec<Cow<'static, str>>> {
+    ///
+    /// Fragments are joined with `\n`, or with the original newline if
+    /// `Options::keep_line_endings` is set.
+    fn verbatim_string_no_peek(&mut self) -> ReaderResult<String> {
         assert_eq!(self.peek().unwrap(), Some(b'|'));
 
-        let mut frags = Vec::new();
+        let mut s = String::new();
         loop {
             self.buf.consume(1);
-            match String::from_utf8(try!(self.non_newline_chars())) {
-                Ok(bytes) => { frags.push(bytes.into()); }
+            let max_len = self.options.max_string_len.saturating_sub(s.len());
+            match String::from_utf8(try!(self.non_newline_chars(max_len))) {
+                Ok(frag) => {
+                    let trailing = frag.len() - frag.trim_right_matches(&[' ', '\t'][..]).len();
+                    if trailing > 0 && self.options.lints {
+                        let mut pos = self.buf.pos;
+                        pos.column -= trailing;
+                        pos.offset -= trailing;
+                        self.warn(pos, "trailing whitespace in a verbatim string is kept");
+                    }
+                    s.push_str(&frag);
+                }
                 Err(_) => { return reader_err("invalid UTF-8 sequence in a verbatim string"); }
             }
-            self.buf.consume(1); // either 0x0a or 0x0d
+            let newline = match try!(self.newline_char_opt()) {
+                Some(newline) => newline,
+                None => { break; } // the end of file
+            };
             try!(self.skip_ws());
-            if try!(self.peek()) != Some(b'|') { break; }
+            if try!(self.peek()) != Some(b'|') {
+                // the newline also terminates the verbatim string itself,
+                // and may be a part of the following `value-separator`
+                self.pending_newline = true;
+                break;
+            }
+            // the newline between fragments also counts
+            let newline = if self.options.keep_line_endings { newline } else { "\n" };
+            if s.len() + newline.len() > self.options.max_string_len {
+                return limit_err(Limit::StringLength, format!("exceeded the maximum string \
+                                                               length of {} bytes",
+                                                              self.options.max_string_len));
+            }
+            s.push_str(newline);
         }
-        Ok(frags)
+        Ok(s)
     }
 
     /// Given a known lookahead, parses `bare-string` where:
@@ -822,32 +2360,154 @@ impl<'a> Reader<'a> {
     ///          / %x3001-D7FF / %xF900-FDCF / %xFDF0-FFFD / %x10000-EFFFF
     /// id-end = id-start / %x2E / %x30-39 / %xB7 / %x0300-036F / %x203F-2040
     /// ~~~~
+    ///
+    /// With `Options::json5`, any character can be also written as `\uXXXX`.
     fn bare_string_no_peek(&mut self) -> ReaderResult<Cow<'static, str>> {
-        assert!(self.peek().ok().and_then(|c| c).map_or(false, is_id_start_byte));
+        let json5 = self.options.json5;
+        assert!(self.peek().ok().and_then(|c| c).map_or(false, |b| {
+            is_id_start_byte(b) || (json5 && b == b'\\')
+        }));
 
         let mut s = String::new();
-        match try!(util::io::read_char(&mut self.buf)) {
+        match try!(self.bare_char()) {
             Some(ch) if is_id_start(ch) => { s.push(ch); }
             Some(_) => { return reader_err("expected a bare string, got an invalid character"); }
             None    => { return reader_err("expected a bare string, got the end of file"); }
         };
-        while try!(self.peek()).map_or(false, is_id_end_byte) {
-            match try!(util::io::read_char(&mut self.buf)) {
+        self.bare_string_rest(s).map(|s| s.into())
+    }
+
+    /// Parses `*id-end` of `bare-string` after `id-start` already read into `s`.
+    fn bare_string_rest(&mut self, mut s: String) -> ReaderResult<String> {
+        let json5 = self.options.json5;
+        while try!(self.peek()).map_or(false, |b| is_id_end_byte(b) || (json5 && b == b'\\')) {
+            if s.len() >= self.options.max_string_len {
+                return limit_err(Limit::StringLength, format!("exceeded the maximum string \
+                                                               length of {} bytes",
+                                                              self.options.max_string_len));
+            }
+            match try!(self.bare_char()) {
                 Some(ch) if is_id_end(ch) => { s.push(ch); }
                 Some(_) => { return reader_err("expected a bare string, got an invalid \
                                                 character"); }
                 None    => { return reader_err("expected a bare string, got the end of file"); }
             };
         }
-        Ok(s.into())
+        Ok(s)
+    }
+
+    /// Interprets a bare string in the value position, which is an extension enabled by
+    /// `Options::bare_values`. Literals keep their meaning, and anything else is a string.
+    fn bare_value(&self, s: String) -> repr::Atom<'static> {
+        match &s[..] {
+            "false" => repr::False,
+            "null" => repr::Null,
+            "true" => repr::True,
+            "NaN" if self.options.nan_infinity => repr::F64(f64::NAN),
+            "Infinity" if self.options.nan_infinity => repr::F64(f64::INFINITY),
+            "-Infinity" if self.options.nan_infinity => repr::F64(f64::NEG_INFINITY),
+            _ => repr::OwnedString(s),
+        }
+    }
+
+    /// Reads a character of `bare-string`, which may be `\uXXXX` with `Options::json5`.
+    fn bare_char(&mut self) -> ReaderResult<Option<char>> {
+        if !self.options.json5 || try!(self.peek()) != Some(b'\\') {
+            return Ok(try!(util::io::read_char(&mut self.buf)));
+        }
+        self.buf.consume(1);
+        if try!(util::io::read_byte(&mut self.buf)) != Some(b'u') {
+            return reader_err("only `\\uXXXX` can be used in a bare key");
+        }
+        let unit = try!(self.unicode_escape_digits());
+        match char::from_u32(unit as u32) {
+            Some(ch) => Ok(Some(ch)),
+            None => reader_err(format!("surrogate `\\u{:04x}` cannot be used in a bare key", unit)),
+        }
+    }
+}
+
+/// An iterator over consecutive documents in the stream, returned by `Reader::documents`.
+pub struct Documents<'r, 'a: 'r> {
+    reader: &'r mut Reader<'a>,
+    done: bool,
+}
+
+impl<'r, 'a> Iterator for Documents<'r, 'a> {
+    type Item = ReaderResult<repr::Atom<'static>>;
+
+    fn next(&mut self) -> Option<ReaderResult<repr::Atom<'static>>> {
+        if self.done { return None; }
+        let ret = self.reader.next_document_opt();
+        match self.reader.locate(ret) {
+            Ok(Some(doc)) => Some(Ok(doc)),
+            Ok(None) => { self.done = true; None }
+            Err(err) => { self.done = true; Some(Err(err)) }
+        }
+    }
+}
+
+/// The state of `Elements` and `Members`.
+#[derive(Clone, Copy)]
+enum StreamState {
+    Start,
+    Items { braced: bool, count: usize },
+    Done,
+}
+
+/// An iterator over elements of the top-level array, returned by `Reader::elements`.
+pub struct Elements<'r, 'a: 'r> {
+    reader: &'r mut Reader<'a>,
+    state: StreamState,
+}
+
+impl<'r, 'a> Iterator for Elements<'r, 'a> {
+    type Item = ReaderResult<repr::Atom<'static>>;
+
+    fn next(&mut self) -> Option<ReaderResult<repr::Atom<'static>>> {
+        let ret = self.reader.next_element_opt(&mut self.state);
+        match self.reader.locate(ret) {
+            Ok(ret) => ret.map(Ok),
+            Err(err) => {
+                self.reader.reset_nesting();
+                self.state = StreamState::Done;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// An iterator over members of the top-level object, returned by `Reader::members`.
+pub struct Members<'r, 'a: 'r> {
+    reader: &'r mut Reader<'a>,
+    state: StreamState,
+}
+
+impl<'r, 'a> Iterator for Members<'r, 'a> {
+    type Item = ReaderResult<(repr::Key<'static>, repr::Atom<'static>)>;
+
+    fn next(&mut self) -> Option<ReaderResult<(repr::Key<'static>, repr::Atom<'static>)>> {
+        let ret = self.reader.next_member_opt(&mut self.state);
+        match self.reader.locate(ret) {
+            Ok(ret) => ret.map(Ok),
+            Err(err) => {
+                self.reader.reset_nesting();
+                self.state = StreamState::Done;
+                Some(Err(err))
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Reader;
+    use std::cell::RefCell;
+    use std::io;
+    use std::io::BufReader;
+    use std::rc::Rc;
+    use super::{Reader, ReaderResult, Options, DuplicateKeys, Position, ErrorKind, Limit};
     use repr;
-    use repr::{Null, True, False, I64, F64};
+    use repr::{Null, True, False, I64, U64, F64};
 
     macro_rules! valid {
         ($buf:expr, $repr:expr) => ({
@@ -866,6 +2526,11 @@ mod tests {
 
     #[allow(non_snake_case)] // make it look like a constructor
     fn String<'a>(s: &'a str) -> repr::Atom<'a> { repr::OwnedString(s.to_string()) }
+    /// Parses a document with given options.
+    fn parse_with(options: Options, s: &str) -> ReaderResult<repr::Atom<'static>> {
+        Reader::with_options(&mut BufReader::new(s.as_bytes()), options).parse_document()
+    }
+
     macro_rules! array { [$($e:expr),*] => (repr::Array(vec![$($e),*])) }
     macro_rules! object { [$($k:expr => $v:expr),*] =>
         (repr::Object(vec![$((repr::Key::new($k), $v)),*].into_iter().collect())) }
@@ -881,7 +2546,7 @@ mod tests {
         valid!("42.0", F64(42.0));
         valid!("0e3", F64(0.0));
         valid!("42e3", F64(42000.0));
-        valid!("72057594037927936", F64(72057594037927936.0)); // 2^56 exceeds integral range
+        valid!("72057594037927936", I64(72057594037927936)); // 2^56 is still exact
         valid!("[1, 2, 3]", array![I64(1), I64(2), I64(3)]);
         valid!("[1\n 2\n 3]", array![I64(1), I64(2), I64(3)]);
         valid!("[null]", array![Null]);
@@ -892,5 +2557,700 @@ mod tests {
         valid!("{\"f\": 1, 'g': 2}", object!["f" => I64(1), "g" => I64(2)]);
         valid!("{f=1\n g=2}", object!["f" => I64(1), "g" => I64(2)]);
     }
+
+    #[test]
+    fn test_truncated_literals() {
+        // the end of file in the middle of a literal is an error, not an endless loop
+        invalid!("[fals");
+        invalid!("tru");
+        invalid!("nul");
+        invalid!("[n");
+    }
+
+    #[test]
+    fn test_integers() {
+        valid!("9007199254740993", I64(9007199254740993)); // 2^53 + 1
+        valid!("-9223372036854775808", I64(-9223372036854775808));
+        valid!("9223372036854775807", I64(9223372036854775807));
+        valid!("9223372036854775808", U64(9223372036854775808));
+        valid!("18446744073709551615", U64(18446744073709551615));
+        // integers not representable as either type fall back to `F64`
+        valid!("18446744073709551616", F64(18446744073709551616.0));
+        valid!("-9223372036854775809", F64(-9223372036854775809.0));
+        // fractions and exponents are always `F64`
+        valid!("9007199254740993.0", F64(9007199254740993.0));
+        valid!("1e2", F64(100.0));
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        fn parse(policy: DuplicateKeys, buf: &str) -> (Result<repr::Atom<'static>, String>,
+                                                        Vec<Position>) {
+            let options = Options { duplicate_keys: policy, ..Options::default() };
+            let mut buf = BufReader::new(buf.as_bytes());
+            let mut reader = Reader::with_options(&mut buf, options);
+            let parsed = reader.parse_document().map_err(|e| e.to_string());
+            (parsed, reader.warnings().iter().map(|w| w.pos).collect())
+        }
+
+        let doc = "a = 1\nb = [2]\na = 3\nb = 4\na = 5";
+        assert_eq!(parse(DuplicateKeys::LastWins, doc),
+                   (Ok(object!["a" => I64(5), "b" => I64(4)]), vec![]));
+        assert_eq!(parse(DuplicateKeys::FirstWins, doc),
+                   (Ok(object!["a" => I64(1), "b" => array![I64(2)]]), vec![]));
+        assert_eq!(parse(DuplicateKeys::Collect, doc),
+                   (Ok(object!["a" => array![I64(1), I64(3), I64(5)],
+                               "b" => array![array![I64(2)], I64(4)]]), vec![]));
+        assert_eq!(parse(DuplicateKeys::Collect, "a = 1\nb = [2]\nc = {d: 3}"),
+                   (Ok(object!["a" => array![I64(1)], "b" => array![array![I64(2)]],
+                               "c" => array![object!["d" => array![I64(3)]]]]), vec![]));
+        assert_eq!(parse(DuplicateKeys::Warn, doc),
+                   (Ok(object!["a" => I64(5), "b" => I64(4)]),
+                    vec![Position { line: 3, column: 1, offset: 14 },
+                         Position { line: 4, column: 1, offset: 20 },
+                         Position { line: 5, column: 1, offset: 26 }]));
+        assert_eq!(parse(DuplicateKeys::Error, doc),
+                   (Err("duplicate key `a` (first defined at line 1, column 1) \
+                         at line 3, column 1".to_string()), vec![]));
+        assert_eq!(parse(DuplicateKeys::Error, "{x: {a: 1}, y: {a: 2}}").0.is_ok(), true);
+    }
+
+    #[cfg(feature = "preserve_order")]
+    #[test]
+    fn test_preserve_order() {
+        let parsed = Reader::parse_document_from_buf(b"z = 1\ny = {c: 2, a: 3, b: 4}\nx = 5");
+        let keys = |atom: &repr::Atom| match *atom {
+            repr::Object(ref o) => o.keys().map(|k| k.to_string()).collect::<Vec<_>>(),
+            _ => panic!("not an object"),
+        };
+        let parsed = parsed.unwrap();
+        assert_eq!(keys(&parsed), ["z", "y", "x"]);
+        if let repr::Object(ref o) = parsed {
+            assert_eq!(keys(o.get(&repr::Key::new("y")).unwrap()), ["c", "a", "b"]);
+        }
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let deep = ::std::iter::repeat('[').take(1000000).collect::<String>();
+        let err = Reader::parse_value_from_buf(deep.as_bytes()).unwrap_err();
+        assert_eq!(err.to_string(), "exceeded the maximum nesting depth of 128 \
+                                     at line 1, column 129");
+
+        fn parse(max_depth: usize, buf: &str) -> bool {
+            parse_with(Options { max_depth: max_depth, ..Options::default() }, buf).is_ok()
+        }
+        assert!(parse(2, "[[1], {a: 2}]"));
+        assert!(!parse(2, "[[[1]]]"));
+        assert!(parse(2, "a = [1]\nb = {c: 2}"));
+        assert!(!parse(2, "a = {b: {}}"));
+        assert!(!parse(0, "[]"));
+
+        // the depth is balanced after errors
+        let mut buf = BufReader::new("a = [[1, {b: ?}]]".as_bytes());
+        let mut reader = Reader::new(&mut buf);
+        assert!(reader.parse_document().is_err());
+        assert_eq!((reader.depth, reader.separators.len()), (0, 0));
+        let mut buf = BufReader::new("a = 1\nb = [[?]]".as_bytes());
+        let mut reader = Reader::new(&mut buf);
+        assert_eq!(reader.members().filter(|m| m.is_err()).count(), 1);
+        assert_eq!((reader.depth, reader.separators.len()), (0, 0));
+    }
+
+    #[test]
+    fn test_limits() {
+        fn parse(options: Options, buf: &str) -> Result<(), ErrorKind> {
+            parse_with(options, buf).map(|_| ()).map_err(|e| e.kind)
+        }
+        let default = Options::default;
+        let exceeded = |limit| Err(ErrorKind::LimitExceeded(limit));
+
+        let options = Options { max_input_bytes: 8, ..default() };
+        assert_eq!(parse(options.clone(), "[1,2,3]\n"), Ok(()));
+        assert_eq!(parse(options.clone(), "[1,2,3]\n\n"), exceeded(Limit::InputBytes));
+        assert_eq!(parse(options.clone(), "[1,2,3,4]"), exceeded(Limit::InputBytes));
+        // nothing is read beyond the limit, even while detecting the encoding
+        let mut cursor = io::Cursor::new(b"\xef\xbb\xbf[1]".to_vec());
+        let options = Options { max_input_bytes: 2, ..default() };
+        assert!(Reader::with_options(&mut cursor, options).parse_document().is_err());
+        assert_eq!(cursor.position(), 2);
+
+        let options = Options { max_string_len: 4, ..default() };
+        assert_eq!(parse(options.clone(), "abcd = ['abcd', |ab\n|c\n]"), Ok(()));
+        assert_eq!(parse(options.clone(), "a = 'abcde'"), exceeded(Limit::StringLength));
+        assert_eq!(parse(options.clone(), "a = '\\u0041bcde'"), exceeded(Limit::StringLength));
+        assert_eq!(parse(options.clone(), "a = |ab\n|cd"), exceeded(Limit::StringLength));
+        assert_eq!(parse(options.clone(), "abcde = 1"), exceeded(Limit::StringLength));
+
+        let options = Options { max_number_len: 4, ..default() };
+        assert_eq!(parse(options.clone(), "[1234, -123, 1.25, 1e10]"), Ok(()));
+        assert_eq!(parse(options.clone(), "[12345]"), exceeded(Limit::NumberLength));
+        assert_eq!(parse(options.clone(), "[1.2345]"), exceeded(Limit::NumberLength));
+        assert_eq!(parse(options.clone(), "[1e+10]"), exceeded(Limit::NumberLength));
+
+        let options = Options { max_array_len: 2, max_object_members: 2, ..default() };
+        assert_eq!(parse(options.clone(), "a = [1, 2]\nb = {c: 3, d: 4}"), Ok(()));
+        assert_eq!(parse(options.clone(), "a = [1, 2, 3]"), exceeded(Limit::ArrayLength));
+        assert_eq!(parse(options.clone(), "a = {b: 1, b: 1, b: 1}"),
+                   exceeded(Limit::ObjectMembers));
+
+        let options = Options { max_nodes: 4, ..default() };
+        assert_eq!(parse(options.clone(), "a = [1, 2]"), Ok(()));
+        assert_eq!(parse(options.clone(), "[[[[1]]]]"), exceeded(Limit::Nodes));
+
+        assert_eq!(parse(default(), "a = 'abc"), Err(ErrorKind::Syntax));
+    }
+
+    #[test]
+    fn test_strict_json() {
+        fn parse(buf: &str) -> Result<repr::Atom<'static>, String> {
+            let options = Options { strict_json: true, ..Options::default() };
+            parse_with(options, buf).map_err(|e| e.cause.into_owned())
+        }
+        assert_eq!(parse(" {\"a\": [1, 2.5e3, true],\r\n\t\"b\": {}} "),
+                   Ok(object!["a" => array![I64(1), F64(2500.0), True], "b" => object![]]));
+        assert_eq!(parse("[\n1\n,\n2\n]"), Ok(array![I64(1), I64(2)]));
+        assert_eq!(parse("\"\\u00e9\\/\""), Ok(String("\u{e9}/")));
+        assert_eq!(parse("42"), Ok(I64(42)));
+
+        let err = |cause: &str| Err(cause.to_string());
+        assert_eq!(parse("{a: 1}"), err("bare keys are not allowed in JSON"));
+        assert_eq!(parse("{\"a\" = 1}"), err("expected `:`, `=` is not allowed in JSON"));
+        assert_eq!(parse("['a']"), err("only double-quoted strings are allowed in JSON"));
+        assert_eq!(parse("{'a': 1}"), err("only double-quoted strings are allowed in JSON"));
+        assert_eq!(parse("[|a\n]"), err("only double-quoted strings are allowed in JSON"));
+        assert_eq!(parse("[1 # one\n]"), err("comments are not allowed in JSON"));
+        assert_eq!(parse("[1\n2]"), err("expected `,`, newlines cannot separate values in JSON"));
+        assert_eq!(parse("[1, 2,]"), err("trailing separators are not allowed in JSON"));
+        assert_eq!(parse("{\"a\": 1,\n}"), err("trailing separators are not allowed in JSON"));
+        assert_eq!(parse("a: 1"), err("braceless documents are not allowed in JSON"));
+        assert_eq!(parse("\"a\": 1"), err("braceless documents are not allowed in JSON"));
+        assert_eq!(parse("\"a\tb\""), err("control characters should be escaped in JSON strings"));
+        assert_eq!(parse("\"\\'\""), err("unknown escape sequence `\\'` in JSON"));
+    }
+
+    #[test]
+    fn test_documents() {
+        fn parse(buf: &str, strict_json: bool) -> Vec<Result<repr::Atom<'static>, String>> {
+            let options = Options { strict_json: strict_json, ..Options::default() };
+            let mut buf = BufReader::new(buf.as_bytes());
+            let mut reader = Reader::with_options(&mut buf, options);
+            let docs = reader.documents().map(|doc| doc.map_err(|e| e.to_string())).collect();
+            docs
+        }
+
+        assert_eq!(parse("", false), []);
+        assert_eq!(parse(" # nothing\n", false), []);
+        assert_eq!(parse("a = 1\nb = 2\n\x1ec = 3\n{d: 4}[5]\n\x1e\x1e  # end\n", false),
+                   [Ok(object!["a" => I64(1), "b" => I64(2)]), Ok(object!["c" => I64(3)]),
+                    Ok(object!["d" => I64(4)]), Ok(array![I64(5)])]);
+        assert_eq!(parse("{a: 1}\nb = 2\n[3]", false),
+                   [Ok(object!["a" => I64(1)]), Ok(object!["b" => I64(2)]), Ok(array![I64(3)])]);
+        assert_eq!(parse("{a: 1}\n\n{b: }\n{c: 3}", false),
+                   [Ok(object!["a" => I64(1)]),
+                    Err("expected value at line 3, column 5".to_string())]);
+        assert_eq!(parse("a = 1 b = 2", false),
+                   [Err("expected end of document at line 1, column 7".to_string())]);
+        assert_eq!(parse("[1]\n2", false),
+                   [Ok(array![I64(1)]), Err("expected document at line 2, column 1".to_string())]);
+        assert_eq!(parse("1 \"two\"\n[3]\x1e{}", true),
+                   [Ok(I64(1)), Ok(String("two")), Ok(array![I64(3)]), Ok(object![])]);
+    }
+
+    #[test]
+    fn test_streaming() {
+        fn elements(buf: &str) -> Vec<Result<repr::Atom<'static>, String>> {
+            let mut buf = BufReader::new(buf.as_bytes());
+            let mut reader = Reader::new(&mut buf);
+            let elements = reader.elements().map(|e| e.map_err(|e| e.to_string())).collect();
+            elements
+        }
+        fn members(buf: &str) -> Vec<Result<(String, repr::Atom<'static>), String>> {
+            let mut buf = BufReader::new(buf.as_bytes());
+            let mut reader = Reader::new(&mut buf);
+            let members = reader.members().map(|m| m.map(|(k, v)| (k.to_string(), v))
+                                                    .map_err(|e| e.to_string())).collect();
+            members
+        }
+
+        assert_eq!(elements(" [1, [2]\n{a: 3}\n] "),
+                   [Ok(I64(1)), Ok(array![I64(2)]), Ok(object!["a" => I64(3)])]);
+        assert_eq!(elements("[]"), []);
+        assert_eq!(elements("[1, 2 3]"),
+                   [Ok(I64(1)), Ok(I64(2)), Err("expected `]` at line 1, column 7".to_string())]);
+        assert_eq!(elements("[1, ?]"),
+                   [Ok(I64(1)), Err("expected `]` at line 1, column 5".to_string())]);
+        assert_eq!(elements("[1] 2"),
+                   [Ok(I64(1)), Err("expected end of file at line 1, column 5".to_string())]);
+        assert_eq!(elements("{}"), [Err("expected `[` at line 1, column 1".to_string())]);
+
+        assert_eq!(members("a = 1\nb = [2]\na = 3\n"),
+                   [Ok(("a".to_string(), I64(1))), Ok(("b".to_string(), array![I64(2)])),
+                    Ok(("a".to_string(), I64(3)))]);
+        assert_eq!(members("{a: 1, b: 2,}"),
+                   [Ok(("a".to_string(), I64(1))), Ok(("b".to_string(), I64(2)))]);
+        assert_eq!(members("{a: 1\nb: }"),
+                   [Ok(("a".to_string(), I64(1))),
+                    Err("expected value at line 2, column 4".to_string())]);
+    }
+
+    #[test]
+    fn test_nan_infinity() {
+        let parse = |s: &str, nan_infinity: bool| {
+            parse_with(Options { nan_infinity: nan_infinity, ..Options::default() }, s)
+        };
+        match parse("[NaN, Infinity, +Infinity, -Infinity, -1]", true) {
+            Ok(repr::Array(ref v)) if v.len() == 5 => {
+                match v[0] { F64(x) if x.is_nan() => {} ref x => panic!("not NaN: {:?}", x) }
+                assert_eq!(&v[1..], &[F64(1.0 / 0.0), F64(1.0 / 0.0), F64(-1.0 / 0.0), I64(-1)]);
+            }
+            ret => panic!("unexpected result {:?}", ret),
+        }
+        assert_eq!(parse("NaN: Infinity", true).unwrap(),
+                   object!("NaN" => F64(1.0 / 0.0)));
+        for s in &["[NaN]", "[Infinity]", "[+Infinity]", "[-Infinity]"] {
+            assert!(parse(s, false).is_err(), "{} should be rejected by default", s);
+        }
+        for s in &["[nan]", "[Inf]", "[+1]", "[-Inf]", "[NaNa]", "[-NaN]"] {
+            assert!(parse(s, true).is_err(), "{} should be rejected", s);
+        }
+    }
+
+    #[test]
+    fn test_extended_numbers() {
+        let parse = |s: &str, extended_numbers: bool| {
+            parse_with(Options { extended_numbers: extended_numbers, ..Options::default() }, s)
+        };
+        assert_eq!(parse("[0xff, 0XFF, -0x10, 0o17, 0b1010_0101, 0xdead_BEEF]", true).unwrap(),
+                   array![I64(255), I64(255), I64(-16), I64(15), I64(0xa5), I64(0xdeadbeef)]);
+        assert_eq!(parse("[1_000_000, -1_0.2_5e1_0, 0.5, 0]", true).unwrap(),
+                   array![I64(1000000), F64(-10.25e10), F64(0.5), I64(0)]);
+        assert_eq!(parse("[0xffffffffffffffff, -0x8000000000000000, 0x7fffffffffffffff]",
+                         true).unwrap(),
+                   array![U64(!0), I64(::std::i64::MIN), I64(::std::i64::MAX)]);
+
+        for s in &["[0x10000000000000000]", "[-0x8000000000000001]", "[0b2]", "[0o8]", "[0x]",
+                   "[0x_1]", "[1__0]", "[1_]", "[1_.5]", "[1._5]", "[1e_5]", "[0x1_]", "[-_1]"] {
+            match parse(s, true) {
+                Err(ref e) if e.kind == ErrorKind::Syntax => {}
+                ret => panic!("{} should be rejected, got {:?}", s, ret),
+            }
+        }
+        let e = parse("[0x10000000000000000]", true).unwrap_err();
+        assert_eq!(&*e.cause, "the integer `0x10000000000000000` does not fit in 64 bits");
+        for s in &["[0xff]", "[0o7]", "[0b1]", "[1_000]"] {
+            assert!(parse(s, false).is_err(), "{} should be rejected by default", s);
+        }
+
+        // the sign and the prefix count towards the maximum length
+        let parse = |s: &str| {
+            let options = Options { extended_numbers: true, max_number_len: 4,
+                                    ..Options::default() };
+            parse_with(options, s).map_err(|e| e.kind)
+        };
+        assert_eq!(parse("[0xff, -0x1, 0b11]").unwrap(), array![I64(255), I64(-1), I64(3)]);
+        for s in &["[0x100]", "[-0x10]", "[0b1_0]"] {
+            assert_eq!(parse(s), Err(ErrorKind::LimitExceeded(Limit::NumberLength)));
+        }
+    }
+
+    #[test]
+    fn test_extended_escapes() {
+        let parse = |s: &str, extended_escapes: bool| {
+            parse_with(Options { extended_escapes: extended_escapes, ..Options::default() }, s)
+        };
+        let s = r#"["\u{1F600}\u{41}\u{0000e9}", '\x41\x7f\0', "\0a", "\u00e9\ud83d\ude00"]"#;
+        assert_eq!(parse(s, true).unwrap(),
+                   array![String("\u{1f600}A\u{e9}"), String("A\u{7f}\u{0}"), String("\u{0}a"),
+                          String("\u{e9}\u{1f600}")]);
+        assert_eq!(parse("['one \\\n    two \\\r\n\tthree\\\r', 4]", true).unwrap(),
+                   array![String("one two three"), I64(4)]);
+
+        let errors = [
+            (r#"["\u{110000}"]"#, "`\\u{110000}` is out of the Unicode range"),
+            (r#"["\u{d800}"]"#, "`\\u{d800}` is a surrogate, which is not a character"),
+            (r#"["\u{1234567}"]"#, "`\\u{...}` can have at most 6 hexadecimal digits"),
+            (r#"["\u{}"]"#, "expected hexadecimal digits in `\\u{...}`"),
+            (r#"["\u{12"]"#, "expected `}` after `\\u{`"),
+            (r#"["\x80"]"#, "`\\x80` is out of the ASCII range, use `\\u{80}` instead"),
+            (r#"["\xg0"]"#, "invalid hexadecimal digits after `\\x`"),
+            (r#"["\01"]"#, "`\\0` cannot be followed by a digit"),
+        ];
+        for &(s, cause) in &errors {
+            match parse(s, true) {
+                Err(ref e) if e.kind == ErrorKind::Syntax => assert_eq!(&*e.cause, cause),
+                ret => panic!("{} should be rejected, got {:?}", s, ret),
+            }
+        }
+        for s in &[r#"["\u{41}"]"#, r#"["\x41"]"#, r#"["\0"]"#, "['a\\\nb']"] {
+            assert!(parse(s, false).is_err(), "{} should be rejected by default", s);
+        }
+    }
+
+    #[test]
+    fn test_json5() {
+        let parse = |s: &str, options: Options| parse_with(options, s);
+        let s = r#"// JSON5 document
+{
+  unquoted: 'and you can quote me on that', /* inline */ \u0061b\u0063: 1,
+  singleQuotes: 'I can use "double quotes" here',
+  lineBreaks: "Look, Mom! \
+No \\n's!",
+  hexadecimal: 0xdecaf,
+  leadingDecimalPoint: .8675309, andTrailing: 8675309.,
+  positiveSign: +1, negative: -.5,
+  escapes: '\v\xff\a\é',
+  /* a
+     block */ infinity: [Infinity, -Infinity, +Infinity],
+  trailingComma: 'in objects', andIn: ['arrays',],
+  "backwardsCompatible": "with JSON",
+}"#;
+        assert_eq!(parse(s, Options::json5()).unwrap(), object! {
+            "unquoted" => String("and you can quote me on that"),
+            "abc" => I64(1),
+            "singleQuotes" => String("I can use \"double quotes\" here"),
+            "lineBreaks" => String("Look, Mom! No \\n's!"),
+            "hexadecimal" => I64(0xdecaf),
+            "leadingDecimalPoint" => F64(0.8675309),
+            "andTrailing" => F64(8675309.0),
+            "positiveSign" => I64(1),
+            "negative" => F64(-0.5),
+            "escapes" => String("\u{b}\u{ff}a\u{e9}"),
+            "infinity" => array![F64(f64::INFINITY), F64(f64::NEG_INFINITY), F64(f64::INFINITY)],
+            "trailingComma" => String("in objects"),
+            "andIn" => array![String("arrays")],
+            "backwardsCompatible" => String("with JSON")
+        });
+        assert_eq!(parse("[\u{b}1\u{c}]", Options::json5()).unwrap(), array![I64(1)]);
+        match parse("[-NaN, +NaN, 0X1F, '\\0a\\\n  b']", Options::json5()) {
+            Ok(repr::Array(ref v)) if v.len() == 4 => {
+                assert!(v[..2].iter().all(|x| match *x { F64(x) => x.is_nan(), _ => false }));
+                assert_eq!(&v[2..], &[I64(31), String("\u{0}a  b")]);
+            }
+            ret => panic!("unexpected result {:?}", ret),
+        }
+
+        for s in &["[.]", "[-.]", "[+]", "[1._5]", "[/ comment]", "[/* open]", r#"["\1"]"#,
+                   r"{\x61: 1}", r"{\ud800: 1}", "[0o17]", "[0b1]", "[1_000]", r"['\u{41}']"] {
+            assert!(parse(s, Options::json5()).is_err(), "{} should be rejected", s);
+        }
+        for s in &["[.5]", "[5.]", "[+1]", "// c\n[]", "[/* c */]", r"['\v']", r"{\u0061: 1}",
+                   "[0x1]"] {
+            assert!(parse(s, Options::default()).is_err(), "{} should be rejected by default", s);
+        }
+    }
+
+    #[test]
+    fn test_bare_values() {
+        let parse = |s: &str, nan_infinity: bool| {
+            let options = Options { bare_values: true, nan_infinity: nan_infinity,
+                                    ..Options::default() };
+            parse_with(options, s)
+        };
+        let s = "mode = production\nlist: [a, -b, c.d-e, true, nullable, null, -1, -, NaN]";
+        assert_eq!(parse(s, false).unwrap(), object! {
+            "mode" => String("production"),
+            "list" => array![String("a"), String("-b"), String("c.d-e"), True, String("nullable"),
+                             Null, I64(-1), String("-"), String("NaN")]
+        });
+        match parse("[NaN, Infinity, -Infinity, -Inf]", true) {
+            Ok(repr::Array(ref v)) if v.len() == 4 => {
+                match v[0] { F64(x) if x.is_nan() => {} ref x => panic!("not NaN: {:?}", x) }
+                assert_eq!(&v[1..], &[F64(1.0 / 0.0), F64(-1.0 / 0.0), String("-Inf")]);
+            }
+            ret => panic!("unexpected result {:?}", ret),
+        }
+        assert!(parse("mode = hello world", false).is_err());
+        assert!(parse("[-1x]", false).is_err());
+        assert!(Reader::parse_document_from_buf(b"mode = production").is_err());
+    }
+
+    #[test]
+    fn test_dotted_keys() {
+        let parse = |s: &str, policy: DuplicateKeys| {
+            let options = Options { dotted_keys: true, duplicate_keys: policy,
+                                    ..Options::default() };
+            parse_with(options, s).map_err(|e| e.to_string())
+        };
+        let s = "server.tls.cert = \"x\"\nserver.port = 80\nserver.tls.key = 'y'\n\
+                 hosts.'example.com'.up = true\nlimits = {a: 1}\nlimits.b = 2\n\"a.b\" = 3";
+        assert_eq!(parse(s, DuplicateKeys::LastWins).unwrap(), object! {
+            "server" => object! {
+                "tls" => object! { "cert" => String("x"), "key" => String("y") },
+                "port" => I64(80)
+            },
+            "hosts" => object! { "example.com" => object! { "up" => True } },
+            "limits" => object! { "a" => I64(1), "b" => I64(2) },
+            "a.b" => I64(3)
+        });
+        assert_eq!(parse("{x.y: [1, {z.w: 2}]}", DuplicateKeys::Error).unwrap(),
+                   object! { "x" => object! { "y" => array![I64(1),
+                                                            object! { "z" => object! {
+                                                                "w" => I64(2) } }] } });
+        assert_eq!(parse("a.b = 1\na.b = 2", DuplicateKeys::FirstWins).unwrap(),
+                   object! { "a" => object! { "b" => I64(1) } });
+        assert_eq!(parse("a = {b: 1}\na.b = 2", DuplicateKeys::FirstWins).unwrap(),
+                   object! { "a" => object! { "b" => I64(1) } });
+        // every value is an array with `Collect`, so dotted keys have nothing to extend
+        assert_eq!(parse("a = {b: 1}\nc = 2\nc = 3", DuplicateKeys::Collect).unwrap(),
+                   object! { "a" => array![object! { "b" => array![I64(1)] }],
+                             "c" => array![I64(2), I64(3)] });
+        assert_eq!(parse("a = 1\nb.c = 2", DuplicateKeys::Collect),
+                   Err("dotted keys cannot be used with `DuplicateKeys::Collect` \
+                        at line 2, column 1".to_string()));
+
+        let errors = [
+            ("a.b = 1\na = 2", "`a` is already defined as an object by dotted keys \
+                                (first defined at line 1, column 1) at line 2, column 1"),
+            ("a = 1\nb = 2\na.b.c = 3", "`a` is already defined as a non-object value \
+                                          (first defined at line 1, column 1) \
+                                          at line 3, column 1"),
+            ("a = {b: 1}\na.b = 2", "duplicate key `a.b` (first defined at line 1, column 1) \
+                                     at line 2, column 1"),
+            ("a.b = 1\n  a.b = 2", "duplicate key `a.b` (first defined at line 1, column 1) \
+                                    at line 2, column 3"),
+            ("a = {b: 1}\na.b.c = 2", "`a.b` is already defined as a non-object value \
+                                       at line 2, column 1"),
+            ("a..b = 1", "a dotted key cannot have an empty name at line 1, column 5"),
+            ("a. = 1", "expected a key after `.` at line 1, column 3"),
+        ];
+        for &(s, cause) in &errors {
+            assert_eq!(parse(s, DuplicateKeys::Error), Err(cause.to_string()));
+        }
+
+        // nothing changes by default
+        assert_eq!(Reader::parse_document_from_buf(b"a.b = 1").unwrap(),
+                   object! { "a.b" => I64(1) });
+        let mut buf = BufReader::new(&b"a.b.c = 1\nd = 2"[..]);
+        let options = Options { dotted_keys: true, ..Options::default() };
+        let members: Vec<_> = Reader::with_options(&mut buf, options).members()
+            .map(|m| m.unwrap()).collect();
+        assert_eq!(members, vec![(repr::Key::new("a"),
+                                  object! { "b" => object! { "c" => I64(1) } }),
+                                 (repr::Key::new("d"), I64(2))]);
+    }
+
+    #[test]
+    fn test_encoding() {
+        fn parse(buf: &[u8], detect_encoding: bool) -> Result<repr::Atom<'static>, String> {
+            let options = Options { detect_encoding: detect_encoding, ..Options::default() };
+            Reader::with_options(&mut BufReader::new(buf), options)
+                .parse_document().map_err(|e| e.to_string())
+        }
+        fn utf16(s: &str, be: bool) -> Vec<u8> {
+            s.encode_utf16().flat_map(|u| {
+                let (hi, lo) = ((u >> 8) as u8, u as u8);
+                if be { vec![hi, lo] } else { vec![lo, hi] }
+            }).collect()
+        }
+        fn utf32(s: &str, be: bool) -> Vec<u8> {
+            s.chars().flat_map(|c| {
+                let mut b = vec![(c as u32 >> 24) as u8, (c as u32 >> 16) as u8,
+                                 (c as u32 >> 8) as u8, c as u32 as u8];
+                if !be { b.reverse(); }
+                b
+            }).collect()
+        }
+
+        let doc = "a = '\u{1f600}\u{e9}'";
+        let expected = Ok(object!["a" => String("\u{1f600}\u{e9}")]);
+        assert_eq!(parse(b"\xef\xbb\xbfa = 1", false), Ok(object!["a" => I64(1)]));
+        assert_eq!(parse(b"\xef\xbb\xbf", false),
+                   Err("expected document at line 1, column 1".to_string()));
+        assert_eq!(parse(&utf16(&format!("\u{feff}{}", doc), false), true), expected);
+        assert_eq!(parse(&utf16(&format!("\u{feff}{}", doc), true), true), expected);
+        assert_eq!(parse(&utf16(doc, false), true), expected);
+        assert_eq!(parse(&utf16(doc, true), true), expected);
+        assert_eq!(parse(&utf32(&format!("\u{feff}{}", doc), false), true), expected);
+        assert_eq!(parse(&utf32(doc, true), true), expected);
+        assert_eq!(parse(b"a = 1", true), Ok(object!["a" => I64(1)]));
+
+        assert_eq!(parse(b"\xef\xbb\xbfa = ?", false),
+                   Err("expected value at line 1, column 5".to_string()));
+        assert_eq!(parse(&utf16("{a: ?}", false), true),
+                   Err("expected value at line 1, column 5".to_string()));
+        assert_eq!(parse(&utf16(doc, false), false),
+                   Err("the input seems to be encoded in UTF-16LE, which requires \
+                        `Options::detect_encoding` at line 1, column 1".to_string()));
+        assert_eq!(parse(b"a\x00=\x00'\x00\x00\xd8'\x00", true),
+                   Err("invalid UTF-16LE sequence at line 1, column 4".to_string()));
+        assert_eq!(parse(b"+/v8a = 1", true),
+                   Err("the input is encoded in UTF-7, which is not supported \
+                        at line 1, column 1".to_string()));
+        assert_eq!(parse(b"\xe9t\xe9 = 1", true),
+                   Err("the input is not encoded in UTF-8 at line 1, column 1".to_string()));
+    }
+
+    #[test]
+    fn test_lossy_utf8() {
+        fn parse(buf: &[u8]) -> (Result<repr::Atom<'static>, String>, Vec<String>) {
+            let options = Options { lossy_utf8: true, ..Options::default() };
+            let mut buf = BufReader::with_capacity(4, buf);
+            let mut reader = Reader::with_options(&mut buf, options);
+            let parsed = reader.parse_document().map_err(|e| e.to_string());
+            (parsed, reader.warnings().iter().map(|w| w.to_string()).collect())
+        }
+
+        let doc = b"\xef\xbb\xbfa\xff = 'x\xe9y' # \xc0\xaf\nb = |\xf0\x9f\x98\nc = 1 # \xe2\x82";
+        let (parsed, warnings) = parse(doc);
+        assert_eq!(parsed, Ok(object!["a\u{fffd}" => String("x\u{fffd}y"),
+                                      "b" => String("\u{fffd}"), "c" => I64(1)]));
+        let replaced = |bytes: &str, offset: usize, line: usize, column: usize| {
+            format!("invalid UTF-8 sequence `{}` at byte offset {} is replaced with U+FFFD \
+                     at line {}, column {}", bytes, offset, line, column)
+        };
+        assert_eq!(warnings, vec![replaced("\\xff", 4, 1, 2), replaced("\\xe9", 10, 1, 8),
+                                  replaced("\\xc0", 16, 1, 14), replaced("\\xaf", 17, 1, 15),
+                                  replaced("\\xf0\\x9f\\x98", 24, 2, 6),
+                                  replaced("\\xe2\\x82", 36, 3, 9)]);
+
+        // replacements not yet read are not reported
+        assert_eq!(parse(b"a = ?\xff"),
+                   (Err("expected value at line 1, column 5".to_string()), vec![]));
+        assert!(Reader::parse_document_from_buf(b"a = '\xff'").is_err());
+    }
+
+    #[test]
+    fn test_lints() {
+        fn parse(s: &str) -> (Result<repr::Atom<'static>, String>, Vec<String>) {
+            let options = Options { lints: true, ..Options::default() };
+            let seen = Rc::new(RefCell::new(Vec::new()));
+            let (parsed, warnings) = {
+                let mut buf = BufReader::new(s.as_bytes());
+                let mut reader = Reader::with_options(&mut buf, options);
+                let seen = seen.clone();
+                reader.set_warning_callback(move |w| seen.borrow_mut().push(w.to_string()));
+                let parsed = reader.parse_document().map_err(|e| e.to_string());
+                (parsed, reader.warnings().iter().map(|w| w.to_string()).collect::<Vec<_>>())
+            };
+            assert_eq!(*seen.borrow(), warnings);
+            (parsed, warnings)
+        }
+
+        let (parsed, warnings) = parse("a = [1, 2\n3]\nb = 1\nb = 0.30000000000000001\n\
+                                        c = |x \n    |y\n\td = 0.5");
+        assert_eq!(parsed, Ok(object!["a" => array![I64(1), I64(2), I64(3)],
+                                      "b" => F64(0.3), "c" => String("x \ny"),
+                                      "d" => F64(0.5)]));
+        assert_eq!(warnings, vec![
+            "`,` and newlines are mixed as separators in the same object or array \
+             at line 1, column 10".to_string(),
+            "the number `0.30000000000000001` is rounded to `0.3` at line 4, column 5"
+                .to_string(),
+            "duplicate key `b` overrides the value at line 3, column 1 at line 4, column 1"
+                .to_string(),
+            "trailing whitespace in a verbatim string is kept at line 5, column 7".to_string(),
+            "a tab is used for indentation at line 7, column 2".to_string(),
+        ]);
+
+        // nothing is suspicious here
+        let doc = "a = [1, 2, 3]\nb = [\n  1\n  2\n]\nc = {x: 1, y: [1\n2]}\nd = 1e300 # \t\n\
+                   e = |x\t\ty\nf = 1.5e-3\ng = -0.0";
+        assert_eq!(parse(doc).1, Vec::<String>::new());
+
+        // lints are off by default
+        let mut buf = BufReader::new("a = [1, 2\n3]\na = 1".as_bytes());
+        let mut reader = Reader::new(&mut buf);
+        assert!(reader.parse_document().is_ok());
+        assert!(reader.warnings().is_empty());
+    }
+
+    #[test]
+    fn test_tags() {
+        fn tagged(tag: &str, value: repr::Atom<'static>) -> repr::Atom<'static> {
+            repr::Tagged(tag.to_string(), Box::new(value))
+        }
+        let parse = |s: &str| {
+            let options = Options { tags: true, max_depth: 4, ..Options::default() };
+            let mut buf = BufReader::new(s.as_bytes());
+            let mut reader = Reader::with_options(&mut buf, options);
+            reader.register_tag("date", |v| match v {
+                repr::OwnedString(ref s) if s.len() == 10 && s.as_bytes()[4] == b'-' => {
+                    Ok(v.clone())
+                }
+                _ => Err("expected YYYY-MM-DD".to_string()),
+            });
+            reader.register_tag("hex", |v| match v {
+                repr::OwnedString(ref s) => u64::from_str_radix(s, 16).map(repr::U64)
+                                                                     .map_err(|e| e.to_string()),
+                _ => Err("expected a string".to_string()),
+            });
+            reader.parse_document().map_err(|e| e.to_string())
+        };
+
+        let s = "since = @date \"2024-01-01\"\nmask = @hex 'ff'\n\
+                 point = @geo.point [1, 2]\nlist = [@a @b\n  null, @c {x: 1}]";
+        assert_eq!(parse(s), Ok(object! {
+            "since" => tagged("date", String("2024-01-01")),
+            "mask" => tagged("hex", U64(255)),
+            "point" => tagged("geo.point", array![I64(1), I64(2)]),
+            "list" => array![tagged("a", tagged("b", Null)),
+                             tagged("c", object! { "x" => I64(1) })]
+        }));
+
+        assert_eq!(parse("a = @date 20240101"),
+                   Err("invalid value for `@date`: expected YYYY-MM-DD at line 1, column 11"
+                           .to_string()));
+        assert_eq!(parse("a = @date"),
+                   Err("expected a value after `@date` at line 1, column 10".to_string()));
+        assert_eq!(parse("a = @ 1"),
+                   Err("expected a tag name after `@` at line 1, column 6".to_string()));
+        assert!(parse("a = @a @b @c @d 1").is_err());
+        assert!(Reader::parse_document_from_buf(b"a = @date \"2024-01-01\"").is_err());
+        assert_eq!(parse("@a {x: 1}"),
+                   Err("tags are not allowed on the document at line 1, column 1".to_string()));
+
+        // the depth is balanced after errors
+        let options = Options { tags: true, ..Options::default() };
+        let mut buf = BufReader::new("a = [@a @b]".as_bytes());
+        let mut reader = Reader::with_options(&mut buf, options);
+        assert!(reader.parse_document().is_err());
+        assert_eq!(reader.depth, 0);
+    }
+
+    #[test]
+    fn test_newlines() {
+        fn parse(buf: &str, keep_line_endings: bool) -> Result<repr::Atom<'static>, String> {
+            let options = Options { keep_line_endings: keep_line_endings, ..Options::default() };
+            parse_with(options, buf).map_err(|e| e.to_string())
+        }
+
+        for &nl in ["\n", "\r\n", "\r"].iter() {
+            let doc = ["a = |x", "  |y # not a comment", "", "  # comment", "  |z",
+                       "b = [|p", "|q", "   1 # one", "]", "d = 'r", "s\\r\\nt'",
+                       "c = |"].join(nl);
+            let expected = object!["a" => String("x\ny # not a comment\nz"),
+                                   "b" => array![String("p\nq"), I64(1)],
+                                   "d" => String("r\ns\r\nt"),
+                                   "c" => String("")];
+            assert_eq!(parse(&doc, false), Ok(expected));
+
+            let (a, b) = (["x", "y # not a comment", "z"].join(nl), ["p", "q"].join(nl));
+            let d = ["r", "s\r\nt"].join(nl);
+            let expected = object!["a" => String(&a),
+                                   "b" => array![String(&b), I64(1)],
+                                   "d" => String(&d),
+                                   "c" => String("")];
+            assert_eq!(parse(&doc, true), Ok(expected));
+
+            let doc = ["a = |x", "  |y", "b = ?"].join(nl);
+            assert_eq!(parse(&doc, false), Err("expected value at line 3, column 5".to_string()));
+        }
+
+        // a kept line ending counts towards the maximum length
+        let options = Options { keep_line_endings: true, max_string_len: 4,
+                                ..Options::default() };
+        let mut buf = BufReader::new("a = |abc\r\n|".as_bytes());
+        let err = Reader::with_options(&mut buf, options).parse_document().unwrap_err();
+        assert_eq!(err.kind, ErrorKind::LimitExceeded(Limit::StringLength));
+    }
+
+    #[test]
+    fn test_error_position() {
+        let err = Reader::parse_document_from_buf(b"a = 1\r\nb = [1, 2\r\n").unwrap_err();
+        assert_eq!(err.pos, Some(Position { line: 3, column: 1, offset: 18 }));
+        let err = Reader::parse_value_from_buf("[\"\u{ac00}\", ?]".as_bytes()).unwrap_err();
+        assert_eq!(err.pos, Some(Position { line: 1, column: 7, offset: 8 }));
+    }
 }
 
@@ -3,13 +3,15 @@
 
 //! An internal representation of CSON data.
 
-use std::fmt;
-use std::borrow::Cow;
+use std::{fmt, slice, vec};
+use std::borrow::{Borrow, Cow};
 use std::ops::Deref;
+use std::iter::FromIterator;
 use std::collections::BTreeMap;
 use serialize::json::{Json, ToJson};
 
-pub use self::Atom::{Null, True, False, I64, U64, F64, OwnedString, Array, Object};
+pub use self::Atom::{Null, True, False, I64, U64, F64, OwnedString, Array, Object,
+                        Tagged};
 
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Slice<'a>(&'a str);
@@ -41,6 +43,8 @@ pub enum Atom<'a> {
     OwnedString(String),
     Array(AtomArray<'a>),
     Object(AtomObject<'a>),
+    /// A value with a tag, as in `@date "2024-01-01"`.
+    Tagged(String, Box<Atom<'a>>),
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
@@ -73,8 +77,15 @@ impl<'a> fmt::Debug for Key<'a> {
 }
 
 pub type AtomArray<'a> = Vec<Atom<'a>>;
+
+/// An object, which is sorted by keys unless the `preserve_order` feature is enabled.
+#[cfg(not(feature = "preserve_order"))]
 pub type AtomObject<'a> = BTreeMap<Key<'a>, Atom<'a>>;
 
+/// An object, which keeps the source order of members as the `preserve_order` feature is enabled.
+#[cfg(feature = "preserve_order")]
+pub type AtomObject<'a> = OrderedMap<Key<'a>, Atom<'a>>;
+
 impl<'a> Atom<'a> {
     pub fn from_json<T: ToJson>(jsonlike: &T) -> Atom<'a> {
         Atom::from_owned_json(jsonlike.to_json())
@@ -107,6 +118,7 @@ impl<'a> Atom<'a> {
             OwnedString(s) => OwnedString(s),
             Array(l) => Array(l.into_iter().map(|e| e.into_parsed()).collect()),
             Object(o) => Object(o.into_iter().map(|(k,v)| (k,v.into_parsed())).collect()),
+            Tagged(tag, v) => Tagged(tag, Box::new(v.into_parsed())),
         }
     }
 
@@ -122,6 +134,202 @@ impl<'a> Atom<'a> {
             Array(l) => Array(l.into_iter().map(|e| e.into_owned()).collect()),
             Object(o) => Object(o.into_iter().map(|(k,v)| (Key::new(k.to_string()),
                                                            v.into_owned())).collect()),
+            Tagged(tag, v) => Tagged(tag, Box::new(v.into_owned())),
+        }
+    }
+}
+
+/// A value along with the comments around it.
+///
+/// Each member of an object and element of an array carries the comment lines directly
+/// before it (without a blank line between) and the comment in the same line after it.
+/// The text of each comment excludes the leading `#`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Annotated<'a> {
+    pub comments_before: Vec<String>,
+    pub comment_after: Option<String>,
+    pub value: AnnotatedValue<'a>,
+}
+
+/// A value whose members or elements are annotated.
+#[derive(Clone, PartialEq, Debug)]
+pub enum AnnotatedValue<'a> {
+    /// Anything other than an object or an array.
+    Scalar(Atom<'a>),
+    Array(Vec<Annotated<'a>>),
+    /// An object. The members are kept in the source order, including duplicate keys.
+    Object(Vec<(Key<'a>, Annotated<'a>)>),
+}
+
+impl<'a> Annotated<'a> {
+    pub fn new(value: AnnotatedValue<'a>) -> Annotated<'a> {
+        Annotated { comments_before: Vec::new(), comment_after: None, value: value }
+    }
+
+    /// Returns the member with given key. The last one wins if there are duplicate keys.
+    pub fn get(&self, key: &str) -> Option<&Annotated<'a>> {
+        match self.value {
+            AnnotatedValue::Object(ref items) => {
+                items.iter().rev().find(|&&(ref k, _)| &**k == key).map(|&(_, ref v)| v)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the element with given index.
+    pub fn index(&self, index: usize) -> Option<&Annotated<'a>> {
+        match self.value {
+            AnnotatedValue::Array(ref items) => items.get(index),
+            _ => None,
+        }
+    }
+
+    /// Discards comments.
+    pub fn to_atom(&self) -> Atom<'a> {
+        match self.value {
+            AnnotatedValue::Scalar(ref atom) => atom.clone(),
+            AnnotatedValue::Array(ref items) => Array(items.iter().map(|v| v.to_atom()).collect()),
+            AnnotatedValue::Object(ref items) => {
+                let mut object = AtomObject::new();
+                for &(ref k, ref v) in items { object.insert(k.clone(), v.to_atom()); }
+                Object(object)
+            }
+        }
+    }
+}
+
+/// A step of a path to a value.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PathItem<'a> {
+    /// A member of an object with given key.
+    Key(&'a str),
+    /// An element of an array with given index.
+    Index(usize),
+}
+
+/// A position in the input stream.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Position {
+    /// The line number, starting from 1.
+    pub line: usize,
+    /// The column number in characters, starting from 1.
+    pub column: usize,
+    /// The byte offset from the beginning of the stream, starting from 0.
+    pub offset: usize,
+}
+
+impl Position {
+    pub(crate) fn start() -> Position {
+        Position { line: 1, column: 1, offset: 0 }
+    }
+
+    /// Calculates the position of the byte offset `offset` in `text`.
+    pub fn from_offset(text: &[u8], offset: usize) -> Position {
+        let mut pos = Position::start();
+        let mut after_cr = false;
+        advance_position(&mut pos, &mut after_cr, &text[..offset]);
+        pos
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// Updates `pos` as if `bytes` have been read.
+/// `\r\n` counts as one line break, `after_cr` remembers if the last byte was `\r`.
+pub(crate) fn advance_position(pos: &mut Position, after_cr: &mut bool, bytes: &[u8]) {
+    for &b in bytes {
+        pos.offset += 1;
+        match b {
+            b'\n' if *after_cr => {}
+            b'\n' | b'\r' => { pos.line += 1; pos.column = 1; }
+            b if b & 0xc0 != 0x80 => { pos.column += 1; } // skip UTF-8 continuation bytes
+            _ => {}
+        }
+        *after_cr = b == b'\r';
+    }
+}
+
+/// A value along with its position in the source.
+///
+/// `start` is the position of the first character and `end` is the position right after
+/// the last character of the value.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Spanned<'a> {
+    pub start: Position,
+    pub end: Position,
+    pub value: SpannedValue<'a>,
+}
+
+/// A key along with its position in the source.
+#[derive(Clone, PartialEq, Debug)]
+pub struct SpannedKey<'a> {
+    pub start: Position,
+    pub end: Position,
+    pub key: Key<'a>,
+}
+
+/// A value whose members or elements are spanned.
+#[derive(Clone, PartialEq, Debug)]
+pub enum SpannedValue<'a> {
+    /// Anything other than an object or an array.
+    Scalar(Atom<'a>),
+    Array(Vec<Spanned<'a>>),
+    /// An object. The members are kept in the source order, including duplicate keys.
+    Object(Vec<(SpannedKey<'a>, Spanned<'a>)>),
+}
+
+impl<'a> Spanned<'a> {
+    /// Returns the member with given key. The last one wins if there are duplicate keys.
+    pub fn get(&self, key: &str) -> Option<&Spanned<'a>> {
+        self.get_member(key).map(|&(_, ref v)| v)
+    }
+
+    /// Returns the key and value of the member with given key.
+    pub fn get_member(&self, key: &str) -> Option<&(SpannedKey<'a>, Spanned<'a>)> {
+        match self.value {
+            SpannedValue::Object(ref items) => items.iter().rev().find(|m| &*m.0.key == key),
+            _ => None,
+        }
+    }
+
+    /// Returns the element with given index.
+    pub fn index(&self, index: usize) -> Option<&Spanned<'a>> {
+        match self.value {
+            SpannedValue::Array(ref items) => items.get(index),
+            _ => None,
+        }
+    }
+
+    /// Returns the value at `path`. An empty path refers to the value itself.
+    pub fn at(&self, path: &[PathItem]) -> Option<&Spanned<'a>> {
+        let mut value = self;
+        for &step in path {
+            let next = match step {
+                PathItem::Key(key) => value.get(key),
+                PathItem::Index(index) => value.index(index),
+            };
+            value = match next {
+                Some(next) => next,
+                None => { return None; }
+            };
+        }
+        Some(value)
+    }
+
+    /// Discards positions.
+    pub fn to_atom(&self) -> Atom<'a> {
+        match self.value {
+            SpannedValue::Scalar(ref atom) => atom.clone(),
+            SpannedValue::Array(ref items) => Array(items.iter().map(|v| v.to_atom()).collect()),
+            SpannedValue::Object(ref items) => {
+                let mut object = AtomObject::new();
+                for &(ref k, ref v) in items { object.insert(k.key.clone(), v.to_atom()); }
+                Object(object)
+            }
         }
     }
 }
@@ -139,7 +347,157 @@ impl<'a> ToJson for Atom<'a> {
             Array(ref l) => Json::Array(l.iter().map(|e| e.to_json()).collect()),
             Object(ref o) => Json::Object(o.iter().map(|(k,v)| (k.to_string(),
                                                                 v.to_json())).collect()),
+            // JSON has no place for tags
+            Tagged(_, ref v) => v.to_json(),
+        }
+    }
+}
+
+/// A map which iterates in the insertion order and still allows a logarithmic lookup by keys.
+///
+/// Its interface mirrors a subset of `BTreeMap`. Replacing the value of an existing key keeps
+/// its original place, and the equality does not depend on the order.
+#[derive(Clone)]
+pub struct OrderedMap<K, V> {
+    entries: Vec<(K, V)>,
+    indices: BTreeMap<K, usize>,
+}
+
+impl<K: Ord + Clone, V> OrderedMap<K, V> {
+    pub fn new() -> OrderedMap<K, V> {
+        OrderedMap { entries: Vec::new(), indices: BTreeMap::new() }
+    }
+
+    pub fn len(&self) -> usize { self.entries.len() }
+
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+
+    pub fn get<Q: ?Sized + Ord>(&self, key: &Q) -> Option<&V> where K: Borrow<Q> {
+        self.indices.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    pub fn get_mut<Q: ?Sized + Ord>(&mut self, key: &Q) -> Option<&mut V> where K: Borrow<Q> {
+        match self.indices.get(key) {
+            Some(&i) => Some(&mut self.entries[i].1),
+            None => None,
         }
     }
+
+    pub fn contains_key<Q: ?Sized + Ord>(&self, key: &Q) -> bool where K: Borrow<Q> {
+        self.indices.contains_key(key)
+    }
+
+    /// Inserts a key-value pair and returns the old value if any.
+    /// A new key is placed at the end, an existing key keeps its place.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(&i) = self.indices.get(&key) {
+            return Some(::std::mem::replace(&mut self.entries[i].1, value));
+        }
+        self.indices.insert(key.clone(), self.entries.len());
+        self.entries.push((key, value));
+        None
+    }
+
+    /// Removes a key and returns its value if any. Later members are shifted to fill the gap.
+    pub fn remove<Q: ?Sized + Ord>(&mut self, key: &Q) -> Option<V> where K: Borrow<Q> {
+        let index = match self.indices.remove(key) {
+            Some(index) => index,
+            None => { return None; }
+        };
+        for i in self.indices.values_mut() {
+            if *i > index { *i -= 1; }
+        }
+        Some(self.entries.remove(index).1)
+    }
+
+    pub fn iter<'b>(&'b self) -> Iter<'b, K, V> {
+        Iter { inner: self.entries.iter() }
+    }
+
+    pub fn keys<'b>(&'b self) -> Box<Iterator<Item=&'b K> + 'b> {
+        Box::new(self.entries.iter().map(|&(ref k, _)| k))
+    }
+
+    pub fn values<'b>(&'b self) -> Box<Iterator<Item=&'b V> + 'b> {
+        Box::new(self.entries.iter().map(|&(_, ref v)| v))
+    }
 }
 
+impl<K: Ord + Clone, V> Default for OrderedMap<K, V> {
+    fn default() -> OrderedMap<K, V> { OrderedMap::new() }
+}
+
+impl<K: Ord + Clone, V: PartialEq> PartialEq for OrderedMap<K, V> {
+    fn eq(&self, other: &OrderedMap<K, V>) -> bool {
+        self.len() == other.len() &&
+            self.iter().all(|(k, v)| other.get(k).map_or(false, |v_| *v == *v_))
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for OrderedMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.entries.iter().map(|&(ref k, ref v)| (k, v))).finish()
+    }
+}
+
+impl<K: Ord + Clone, V> FromIterator<(K, V)> for OrderedMap<K, V> {
+    fn from_iter<T: IntoIterator<Item=(K, V)>>(iter: T) -> OrderedMap<K, V> {
+        let mut map = OrderedMap::new();
+        for (k, v) in iter { map.insert(k, v); }
+        map
+    }
+}
+
+impl<K, V> IntoIterator for OrderedMap<K, V> {
+    type Item = (K, V);
+    type IntoIter = vec::IntoIter<(K, V)>;
+    fn into_iter(self) -> vec::IntoIter<(K, V)> { self.entries.into_iter() }
+}
+
+impl<'b, K: Ord + Clone, V> IntoIterator for &'b OrderedMap<K, V> {
+    type Item = (&'b K, &'b V);
+    type IntoIter = Iter<'b, K, V>;
+    fn into_iter(self) -> Iter<'b, K, V> { self.iter() }
+}
+
+/// An iterator over the members of `OrderedMap` in the insertion order.
+pub struct Iter<'b, K: 'b, V: 'b> {
+    inner: slice::Iter<'b, (K, V)>,
+}
+
+impl<'b, K, V> Iterator for Iter<'b, K, V> {
+    type Item = (&'b K, &'b V);
+    fn next(&mut self) -> Option<(&'b K, &'b V)> {
+        self.inner.next().map(|&(ref k, ref v)| (k, v))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) { self.inner.size_hint() }
+}
+
+impl<'b, K, V> DoubleEndedIterator for Iter<'b, K, V> {
+    fn next_back(&mut self) -> Option<(&'b K, &'b V)> {
+        self.inner.next_back().map(|&(ref k, ref v)| (k, v))
+    }
+}
+
+impl<'b, K, V> ExactSizeIterator for Iter<'b, K, V> {}
+
+#[cfg(test)]
+mod tests {
+    use super::OrderedMap;
+
+    #[test]
+    fn test_ordered_map() {
+        let mut map: OrderedMap<&str, i32> =
+            vec![("b", 1), ("a", 2), ("c", 3)].into_iter().collect();
+        assert_eq!(map.insert("a", 4), Some(2));
+        assert_eq!(map.insert("d", 5), None);
+        assert_eq!(map.iter().collect::<Vec<_>>(),
+                   [(&"b", &1), (&"a", &4), (&"c", &3), (&"d", &5)]);
+        assert_eq!(map.remove("a"), Some(4));
+        assert_eq!(map.remove("a"), None);
+        assert_eq!(map.get("d"), Some(&5));
+        assert_eq!(map.keys().cloned().collect::<Vec<_>>(), ["b", "c", "d"]);
+        let other: OrderedMap<&str, i32> = vec![("d", 5), ("c", 3), ("b", 1)].into_iter().collect();
+        assert_eq!(map, other);
+    }
+}
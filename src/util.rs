@@ -20,7 +20,7 @@ pub mod io {
                     0 => {
                         zeroes += 1;
                         if zeroes >= NO_PROGRESS_LIMIT {
-                            break;
+                            return Ok(ReadBytes::NotEnough(read));
                         }
                     }
                     n => {
@@ -150,3 +150,177 @@ pub mod char {
         }
     }
 }
+
+pub mod encoding {
+    use std::{cmp, fmt};
+    use std::io::{BufRead, Error, ErrorKind, Read};
+    use std::io::Result as IoResult;
+
+    /// An encoding of the input stream which can be converted to UTF-8.
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub enum Encoding { Utf16Le, Utf16Be, Utf32Le, Utf32Be }
+
+    impl Encoding {
+        fn unit_size(&self) -> usize {
+            match *self {
+                Encoding::Utf16Le | Encoding::Utf16Be => 2,
+                Encoding::Utf32Le | Encoding::Utf32Be => 4,
+            }
+        }
+
+        fn unit(&self, b: &[u8]) -> u32 {
+            match *self {
+                Encoding::Utf16Le => (b[0] as u32) | (b[1] as u32) << 8,
+                Encoding::Utf16Be => (b[0] as u32) << 8 | (b[1] as u32),
+                Encoding::Utf32Le => (b[0] as u32) | (b[1] as u32) << 8 |
+                                     (b[2] as u32) << 16 | (b[3] as u32) << 24,
+                Encoding::Utf32Be => (b[0] as u32) << 24 | (b[1] as u32) << 16 |
+                                     (b[2] as u32) << 8 | (b[3] as u32),
+            }
+        }
+    }
+
+    impl fmt::Display for Encoding {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(match *self {
+                Encoding::Utf16Le => "UTF-16LE",
+                Encoding::Utf16Be => "UTF-16BE",
+                Encoding::Utf32Le => "UTF-32LE",
+                Encoding::Utf32Be => "UTF-32BE",
+            })
+        }
+    }
+
+    /// The result of `detect`.
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub enum Detected {
+        /// UTF-8, possibly with a byte order mark of given length.
+        Utf8(usize),
+        /// An encoding to be converted, possibly with a byte order mark of given length.
+        Other(Encoding, usize),
+        /// An encoding which is recognized by its byte order mark but not supported.
+        Unsupported(&'static str),
+    }
+
+    /// Detects the encoding from the first (up to) four bytes of the stream.
+    ///
+    /// Byte order marks are recognized first. Otherwise UTF-16 and UTF-32 are detected from
+    /// the pattern of null bytes, assuming that the first character is in ASCII (RFC 4627).
+    pub fn detect(head: &[u8]) -> Detected {
+        match *head {
+            [0xef, 0xbb, 0xbf, ..] => Detected::Utf8(3),
+            [0xff, 0xfe, 0, 0, ..] => Detected::Other(Encoding::Utf32Le, 4),
+            [0, 0, 0xfe, 0xff, ..] => Detected::Other(Encoding::Utf32Be, 4),
+            [0xff, 0xfe, ..] => Detected::Other(Encoding::Utf16Le, 2),
+            [0xfe, 0xff, ..] => Detected::Other(Encoding::Utf16Be, 2),
+            [0x2b, 0x2f, 0x76, ..] => Detected::Unsupported("UTF-7"),
+            [0xf7, 0x64, 0x4c, ..] => Detected::Unsupported("UTF-1"),
+            [0xdd, 0x73, 0x66, 0x73, ..] => Detected::Unsupported("UTF-EBCDIC"),
+            [0x0e, 0xfe, 0xff, ..] => Detected::Unsupported("SCSU"),
+            [0xfb, 0xee, 0x28, ..] => Detected::Unsupported("BOCU-1"),
+            [0x84, 0x31, 0x95, 0x33, ..] => Detected::Unsupported("GB18030"),
+            [0, 0, 0, a, ..] if a != 0 => Detected::Other(Encoding::Utf32Be, 0),
+            [a, 0, 0, 0, ..] if a != 0 => Detected::Other(Encoding::Utf32Le, 0),
+            [0, a, ..] if a != 0 => Detected::Other(Encoding::Utf16Be, 0),
+            [a, 0, ..] if a != 0 => Detected::Other(Encoding::Utf16Le, 0),
+            _ => Detected::Utf8(0),
+        }
+    }
+
+    /// Converts a stream in given encoding to UTF-8.
+    /// An invalid code unit sequence results in an error of `ErrorKind::InvalidData`.
+    pub struct Decoder<R> {
+        inner: R,
+        encoding: Encoding,
+        /// Raw bytes which do not make a complete character yet.
+        pending: Vec<u8>,
+        /// Decoded bytes, of which `out[outpos..]` have not been consumed yet.
+        out: Vec<u8>,
+        outpos: usize,
+        /// True when `pending` starts with an invalid sequence.
+        /// The error is deferred until every preceding character is consumed.
+        invalid: bool,
+    }
+
+    impl<R: BufRead> Decoder<R> {
+        pub fn new(inner: R, encoding: Encoding) -> Decoder<R> {
+            Decoder { inner: inner, encoding: encoding, pending: Vec::new(),
+                      out: Vec::new(), outpos: 0, invalid: false }
+        }
+
+        /// Decodes as many complete characters in `pending` as possible into `out`.
+        /// Stops and sets `invalid` at an invalid sequence.
+        fn decode_pending(&mut self) {
+            let size = self.encoding.unit_size();
+            let mut i = 0;
+            while i + size <= self.pending.len() {
+                let mut code = self.encoding.unit(&self.pending[i..]);
+                let mut width = size;
+                if size == 2 && 0xd800 <= code && code <= 0xdbff {
+                    if i + 4 > self.pending.len() { break; } // wait for the trailing surrogate
+                    let second = self.encoding.unit(&self.pending[i+2..]);
+                    if !(0xdc00 <= second && second <= 0xdfff) {
+                        self.invalid = true;
+                        break;
+                    }
+                    code = 0x10000 + ((code - 0xd800) << 10 | (second - 0xdc00));
+                    width = 4;
+                }
+                if (0xd800 <= code && code <= 0xdfff) || code > 0x10ffff {
+                    self.invalid = true;
+                    break;
+                }
+                let mut buf = [0u8; 4];
+                let len = super::char::encode_utf8_raw(code, &mut buf).unwrap();
+                self.out.extend(buf[..len].iter().cloned());
+                i += width;
+            }
+            self.pending.drain(..i);
+        }
+    }
+
+    impl<R: BufRead> Read for Decoder<R> {
+        fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+            let n = {
+                let avail = try!(self.fill_buf());
+                let n = cmp::min(avail.len(), buf.len());
+                buf[..n].copy_from_slice(&avail[..n]);
+                n
+            };
+            self.consume(n);
+            Ok(n)
+        }
+    }
+
+    impl<R: BufRead> BufRead for Decoder<R> {
+        fn fill_buf(&mut self) -> IoResult<&[u8]> {
+            while self.outpos >= self.out.len() {
+                self.out.clear();
+                self.outpos = 0;
+                if self.invalid {
+                    return Err(Error::new(ErrorKind::InvalidData,
+                                          format!("invalid {} sequence", self.encoding)));
+                }
+                let len = {
+                    let raw = try!(self.inner.fill_buf());
+                    self.pending.extend(raw.iter().cloned());
+                    raw.len()
+                };
+                self.inner.consume(len);
+                if len == 0 {
+                    if !self.pending.is_empty() {
+                        return Err(Error::new(ErrorKind::InvalidData,
+                                              format!("incomplete {} sequence", self.encoding)));
+                    }
+                    break;
+                }
+                self.decode_pending();
+            }
+            Ok(&self.out[self.outpos..])
+        }
+
+        fn consume(&mut self, amt: usize) {
+            self.outpos = cmp::min(self.outpos + amt, self.out.len());
+        }
+    }
+}
@@ -1,4 +1,507 @@
 // This is a part of CSON-rust.
 // Written by Kang Seonghoon. See README.md for details.
 
+//! A CSON writer.
 
+use std::io;
+use std::io::Write;
+use super::repr;
+use super::repr::AnnotatedValue;
+use super::reader::{is_id_start, is_id_end};
+use super::lexer::Quote;
+
+/// A separator between object members or array elements.
+/// Either way every member or element is written in its own line.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Separator {
+    /// Newlines only.
+    Newline,
+    /// A comma after every member or element except for the last one.
+    Comma,
+}
+
+/// A separator between the name and value of a member.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NameSeparator {
+    /// `name: value`.
+    Colon,
+    /// `name = value`.
+    Equals,
+}
+
+/// A radix for writing integers.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Radix {
+    Decimal,
+    /// `0x` followed by lowercase hexadecimal digits.
+    Hexadecimal,
+    /// `0o` followed by octal digits.
+    Octal,
+    /// `0b` followed by binary digits.
+    Binary,
+}
+
+/// Options for `Writer`.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// The string for one level of indentation. Defaults to two spaces.
+    pub indent: String,
+    /// The newline string. Defaults to `\n`.
+    pub newline: String,
+    /// Defaults to `Separator::Newline`.
+    pub separator: Separator,
+    /// Defaults to `NameSeparator::Colon`.
+    pub name_separator: NameSeparator,
+    /// Whether keys are written as bare strings whenever possible. Defaults to true.
+    pub bare_keys: bool,
+    /// Whether string values are written as bare strings when they would be read back
+    /// as the same string with `reader::Options::bare_values`. Defaults to false.
+    pub bare_values: bool,
+    /// The quotation mark for quoted strings. Defaults to `Quote::Double`.
+    pub quote: Quote,
+    /// Whether strings with newlines are written as verbatim strings. Defaults to true.
+    ///
+    /// This has no effect with `Separator::Comma`, as a comma after a verbatim string
+    /// would become a part of the string.
+    pub verbatim_strings: bool,
+    /// Whether the top-level object of a document is written without braces.
+    /// Defaults to true.
+    pub braceless: bool,
+    /// Whether NaN and infinities are written as `NaN`, `Infinity` and `-Infinity`,
+    /// which can be read with `reader::Options::nan_infinity`.
+    /// Otherwise writing them is an error. Defaults to false.
+    pub nan_infinity: bool,
+    /// The radix for `I64` and `U64`. Anything other than `Radix::Decimal` can be read
+    /// with `reader::Options::extended_numbers`. Defaults to `Radix::Decimal`.
+    ///
+    /// This applies to every integer, since `repr::Atom` does not record how a number was
+    /// written. To keep other numbers as written, edit the document with `cst::Document`
+    /// parsed with `reader::Options::extended_numbers`.
+    pub radix: Radix,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            indent: "  ".to_string(),
+            newline: "\n".to_string(),
+            separator: Separator::Newline,
+            name_separator: NameSeparator::Colon,
+            bare_keys: true,
+            bare_values: false,
+            quote: Quote::Double,
+            verbatim_strings: true,
+            braceless: true,
+            nan_infinity: false,
+            radix: Radix::Decimal,
+        }
+    }
+}
+
+fn writer_err<T>(cause: &str) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidInput, cause))
+}
+
+/// Returns true if `s` can be written as `bare-string`.
+fn is_bare_string(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if is_id_start(c) => chars.all(is_id_end),
+        _ => false,
+    }
+}
+
+/// Returns true if `s` can be written as a bare string value.
+/// Literals and anything which would start a number are excluded.
+fn is_bare_value(s: &str) -> bool {
+    match s {
+        "false" | "null" | "true" | "NaN" | "Infinity" | "-Infinity" => { return false; }
+        _ => {}
+    }
+    if s.starts_with('-') {
+        match s[1..].chars().next() {
+            Some('0'...'9') | Some('.') => { return false; }
+            _ => {}
+        }
+    }
+    is_bare_string(s)
+}
+
+pub struct Writer<'a> {
+    out: &'a mut (Write + 'a),
+    options: Options,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(out: &'a mut (Write + 'a)) -> Writer<'a> {
+        Writer::with_options(out, Options::default())
+    }
+
+    pub fn with_options(out: &'a mut (Write + 'a), options: Options) -> Writer<'a> {
+        Writer { out: out, options: options }
+    }
+
+    /// Writes a document, which should be an object or an array, followed by a newline.
+    pub fn write_document(&mut self, atom: &repr::Atom) -> io::Result<()> {
+        match *atom {
+            repr::Object(ref items) if self.options.braceless && !items.is_empty() => {
+                let len = items.len();
+                for (i, (key, value)) in items.iter().enumerate() {
+                    try!(self.write_member(key, value, ""));
+                    try!(self.write_separator(i + 1 == len));
+                }
+                Ok(())
+            }
+            repr::Object(_) | repr::Array(_) => {
+                try!(self.write_value(atom, ""));
+                self.out.write_all(self.options.newline.as_bytes())
+            }
+            _ => writer_err("a document should be an object or an array"),
+        }
+    }
+
+    /// Writes an annotated document, which should be an object or an array,
+    /// followed by a newline. Comments are written around each member and element.
+    pub fn write_annotated_document(&mut self, doc: &repr::Annotated) -> io::Result<()> {
+        match doc.value {
+            AnnotatedValue::Object(ref items) if self.options.braceless && !items.is_empty() => {
+                // the comments before the document go before the first member
+                try!(self.write_comments(&doc.comments_before, ""));
+                for (i, &(ref key, ref value)) in items.iter().enumerate() {
+                    try!(self.write_annotated_item(Some(key), value, "", i + 1 == items.len()));
+                    try!(self.out.write_all(self.options.newline.as_bytes()));
+                }
+                Ok(())
+            }
+            AnnotatedValue::Object(_) | AnnotatedValue::Array(_) => {
+                try!(self.write_annotated_item(None, doc, "", true));
+                self.out.write_all(self.options.newline.as_bytes())
+            }
+            AnnotatedValue::Scalar(_) => writer_err("a document should be an object or an array"),
+        }
+    }
+
+    /// Writes an annotated value which starts in a line indented by `indent`.
+    /// The comments of the value itself are not written.
+    pub fn write_annotated_value(&mut self, value: &repr::Annotated,
+                                 indent: &str) -> io::Result<()> {
+        match value.value {
+            AnnotatedValue::Scalar(ref atom) => self.write_value(atom, indent),
+            AnnotatedValue::Array(ref items) => {
+                if items.is_empty() { return self.out.write_all(b"[]"); }
+                let inner = format!("{}{}", indent, self.options.indent);
+                try!(self.out.write_all(b"["));
+                for (i, item) in items.iter().enumerate() {
+                    try!(self.write_newline(&inner));
+                    try!(self.write_annotated_item(None, item, &inner, i + 1 == items.len()));
+                }
+                try!(self.write_newline(indent));
+                self.out.write_all(b"]")
+            }
+            AnnotatedValue::Object(ref items) => {
+                if items.is_empty() { return self.out.write_all(b"{}"); }
+                let inner = format!("{}{}", indent, self.options.indent);
+                try!(self.out.write_all(b"{"));
+                for (i, &(ref key, ref value)) in items.iter().enumerate() {
+                    try!(self.write_newline(&inner));
+                    try!(self.write_annotated_item(Some(key), value, &inner,
+                                                   i + 1 == items.len()));
+                }
+                try!(self.write_newline(indent));
+                self.out.write_all(b"}")
+            }
+        }
+    }
+
+    /// Writes a member or element with its comments and separator.
+    fn write_annotated_item(&mut self, key: Option<&str>, value: &repr::Annotated,
+                            indent: &str, last: bool) -> io::Result<()> {
+        try!(self.write_comments(&value.comments_before, indent));
+        if let Some(key) = key {
+            try!(self.write_key(key));
+            try!(self.write_name_separator());
+        }
+        match (&value.value, &value.comment_after) {
+            // a comment after a verbatim string would become a part of the string
+            (&AnnotatedValue::Scalar(repr::OwnedString(ref s)), &Some(_)) => {
+                try!(self.write_quoted_string(s));
+            }
+            _ => { try!(self.write_annotated_value(value, indent)); }
+        }
+        try!(self.write_separator_opt(last));
+        if let Some(ref comment) = value.comment_after {
+            try!(write!(self.out, " #{}", comment));
+        }
+        Ok(())
+    }
+
+    /// Writes comment lines, each followed by a newline and `indent`.
+    fn write_comments(&mut self, comments: &[String], indent: &str) -> io::Result<()> {
+        for comment in comments {
+            try!(write!(self.out, "#{}", comment));
+            try!(self.write_newline(indent));
+        }
+        Ok(())
+    }
+
+    /// Writes a value which starts in a line indented by `indent`.
+    /// The following lines, if any, are indented relative to `indent`.
+    pub fn write_value(&mut self, atom: &repr::Atom, indent: &str) -> io::Result<()> {
+        match *atom {
+            repr::Null => self.out.write_all(b"null"),
+            repr::True => self.out.write_all(b"true"),
+            repr::False => self.out.write_all(b"false"),
+            repr::I64(v) if v < 0 => {
+                try!(self.out.write_all(b"-"));
+                self.write_integer((v as u64).wrapping_neg())
+            }
+            repr::I64(v) => self.write_integer(v as u64),
+            repr::U64(v) => self.write_integer(v),
+            repr::F64(v) if v.is_nan() || v.is_infinite() => {
+                if !self.options.nan_infinity {
+                    writer_err("NaN and infinities cannot be written")
+                } else if v.is_nan() {
+                    self.out.write_all(b"NaN")
+                } else if v > 0.0 {
+                    self.out.write_all(b"Infinity")
+                } else {
+                    self.out.write_all(b"-Infinity")
+                }
+            }
+            repr::F64(v) => write!(self.out, "{:?}", v),
+            repr::OwnedString(ref s) => {
+                if self.options.bare_values && is_bare_value(s) {
+                    self.out.write_all(s.as_bytes())
+                } else if self.options.verbatim_strings &&
+                          self.options.separator == Separator::Newline &&
+                          s.contains('\n') && !s.contains('\r') {
+                    self.write_verbatim_string(s, indent)
+                } else {
+                    self.write_quoted_string(s)
+                }
+            }
+            repr::Array(ref items) => {
+                if items.is_empty() { return self.out.write_all(b"[]"); }
+                let inner = format!("{}{}", indent, self.options.indent);
+                try!(self.out.write_all(b"["));
+                for (i, item) in items.iter().enumerate() {
+                    try!(self.write_newline(&inner));
+                    try!(self.write_value(item, &inner));
+                    try!(self.write_separator_opt(i + 1 == items.len()));
+                }
+                try!(self.write_newline(indent));
+                self.out.write_all(b"]")
+            }
+            repr::Object(ref items) => {
+                if items.is_empty() { return self.out.write_all(b"{}"); }
+                let inner = format!("{}{}", indent, self.options.indent);
+                let len = items.len();
+                try!(self.out.write_all(b"{"));
+                for (i, (key, value)) in items.iter().enumerate() {
+                    try!(self.write_newline(&inner));
+                    try!(self.write_member(key, value, &inner));
+                    try!(self.write_separator_opt(i + 1 == len));
+                }
+                try!(self.write_newline(indent));
+                self.out.write_all(b"}")
+            }
+            repr::Tagged(ref tag, ref value) => {
+                if !is_bare_string(tag) {
+                    return writer_err("a tag should be a valid bare string");
+                }
+                try!(write!(self.out, "@{} ", tag));
+                self.write_value(value, indent)
+            }
+        }
+    }
+
+    /// Writes the absolute value of an integer in the configured radix.
+    fn write_integer(&mut self, v: u64) -> io::Result<()> {
+        match self.options.radix {
+            Radix::Decimal => write!(self.out, "{}", v),
+            Radix::Hexadecimal => write!(self.out, "0x{:x}", v),
+            Radix::Octal => write!(self.out, "0o{:o}", v),
+            Radix::Binary => write!(self.out, "0b{:b}", v),
+        }
+    }
+
+    /// Writes a member in a line indented by `indent`, without a separator.
+    pub fn write_member(&mut self, key: &str, value: &repr::Atom,
+                        indent: &str) -> io::Result<()> {
+        try!(self.write_key(key));
+        try!(self.write_name_separator());
+        self.write_value(value, indent)
+    }
+
+    fn write_name_separator(&mut self) -> io::Result<()> {
+        match self.options.name_separator {
+            NameSeparator::Colon => self.out.write_all(b": "),
+            NameSeparator::Equals => self.out.write_all(b" = "),
+        }
+    }
+
+    /// Writes a key, as a bare string if possible and allowed.
+    /// A key with `.` is always quoted so that it is not read back as dotted keys.
+    pub fn write_key(&mut self, key: &str) -> io::Result<()> {
+        if self.options.bare_keys && is_bare_string(key) && !key.contains('.') {
+            self.out.write_all(key.as_bytes())
+        } else {
+            self.write_quoted_string(key)
+        }
+    }
+
+    /// Writes a quoted string with the configured quotation mark.
+    pub fn write_quoted_string(&mut self, s: &str) -> io::Result<()> {
+        let quote = match self.options.quote { Quote::Double => '"', Quote::Single => '\'' };
+        let mut buf = String::with_capacity(s.len() + 2);
+        buf.push(quote);
+        for c in s.chars() {
+            match c {
+                '\\' => buf.push_str("\\\\"),
+                '\x08' => buf.push_str("\\b"),
+                '\x0c' => buf.push_str("\\f"),
+                '\n' => buf.push_str("\\n"),
+                '\r' => buf.push_str("\\r"),
+                '\t' => buf.push_str("\\t"),
+                c if c == quote => { buf.push('\\'); buf.push(c); }
+                c if c < '\x20' || c == '\x7f' => buf.push_str(&format!("\\u{:04x}", c as u32)),
+                c => buf.push(c),
+            }
+        }
+        buf.push(quote);
+        self.out.write_all(buf.as_bytes())
+    }
+
+    fn write_verbatim_string(&mut self, s: &str, indent: &str) -> io::Result<()> {
+        let inner = format!("{}{}", indent, self.options.indent);
+        for (i, line) in s.split('\n').enumerate() {
+            if i > 0 { try!(self.write_newline(&inner)); }
+            try!(self.out.write_all(b"|"));
+            try!(self.out.write_all(line.as_bytes()));
+        }
+        Ok(())
+    }
+
+    fn write_newline(&mut self, indent: &str) -> io::Result<()> {
+        try!(self.out.write_all(self.options.newline.as_bytes()));
+        self.out.write_all(indent.as_bytes())
+    }
+
+    /// Writes a separator after an item, which is a comma if needed.
+    fn write_separator_opt(&mut self, last: bool) -> io::Result<()> {
+        if !last && self.options.separator == Separator::Comma {
+            try!(self.out.write_all(b","));
+        }
+        Ok(())
+    }
+
+    /// Writes a separator and a newline after a top-level member.
+    fn write_separator(&mut self, last: bool) -> io::Result<()> {
+        try!(self.write_separator_opt(last));
+        self.out.write_all(self.options.newline.as_bytes())
+    }
+}
+
+/// Writes a document to a string with the default options.
+pub fn to_string(atom: &repr::Atom) -> io::Result<String> {
+    to_string_with_options(atom, Options::default())
+}
+
+/// Writes an annotated document to a string with given options.
+pub fn annotated_to_string(doc: &repr::Annotated, options: Options) -> io::Result<String> {
+    let mut buf = Vec::new();
+    try!(Writer::with_options(&mut buf, options).write_annotated_document(doc));
+    Ok(String::from_utf8(buf).unwrap())
+}
+
+/// Writes a document to a string with given options.
+pub fn to_string_with_options(atom: &repr::Atom, options: Options) -> io::Result<String> {
+    let mut buf = Vec::new();
+    try!(Writer::with_options(&mut buf, options).write_document(atom));
+    Ok(String::from_utf8(buf).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_string, to_string_with_options, Options, Separator, NameSeparator, Radix};
+    use reader;
+    use reader::Reader;
+    use repr;
+    use repr::{Null, True, I64, U64, F64};
+
+    #[allow(non_snake_case)] // make it look like a constructor
+    fn String(s: &str) -> repr::Atom<'static> { repr::OwnedString(s.to_string()) }
+
+    #[test]
+    fn test_writer() {
+        let atom = Reader::parse_document_from_buf(b"
+            name: 'cson'
+            'needs quotes': [1, -2.5, true, null, {}, []]
+            text: 'line one\\nline two'
+            nested: {a: \"q\\\"uote\\u0001\"}
+        ").unwrap();
+        let written = to_string(&atom).unwrap();
+        assert_eq!(Reader::parse_document_from_buf(written.as_bytes()).unwrap(), atom);
+
+        let atom = repr::Object(vec![(repr::Key::new("a"), repr::Array(vec![I64(1), Null])),
+                                     (repr::Key::new("b c"), String("x\ny"))]
+                                    .into_iter().collect());
+        assert_eq!(to_string(&atom).unwrap(), "a: [\n  1\n  null\n]\n\"b c\": |x\n  |y\n");
+
+        let options = Options { separator: Separator::Comma, name_separator: NameSeparator::Equals,
+                                braceless: false, indent: "\t".to_string(),
+                                ..Options::default() };
+        assert_eq!(to_string_with_options(&atom, options).unwrap(),
+                   "{\n\ta = [\n\t\t1,\n\t\tnull\n\t],\n\t\"b c\" = \"x\\ny\"\n}\n");
+
+        assert!(to_string(&repr::Array(vec![F64(1.0 / 0.0)])).is_err());
+        let options = Options { nan_infinity: true, ..Options::default() };
+        let atom = repr::Array(vec![F64(1.0 / 0.0), F64(-1.0 / 0.0), F64(0.0 / 0.0)]);
+        assert_eq!(to_string_with_options(&atom, options).unwrap(),
+                   "[\n  Infinity\n  -Infinity\n  NaN\n]\n");
+
+        let atom = repr::Array(vec![I64(255), I64(-16), I64(::std::i64::MIN), U64(!0), F64(1.5)]);
+        let options = Options { radix: Radix::Hexadecimal, separator: Separator::Comma,
+                                ..Options::default() };
+        assert_eq!(to_string_with_options(&atom, options).unwrap(),
+                   "[\n  0xff,\n  -0x10,\n  -0x8000000000000000,\n  0xffffffffffffffff,\n  \
+                    1.5\n]\n");
+        let options = Options { radix: Radix::Binary, ..Options::default() };
+        assert_eq!(to_string_with_options(&repr::Array(vec![I64(5)]), options).unwrap(),
+                   "[\n  0b101\n]\n");
+        assert!(to_string(&True).is_err());
+
+        let atom = repr::Array(["production", "-x", "a.b", "true", "-1x", "-.5", "b c", "NaN"]
+                                   .iter().map(|s| String(s)).collect());
+        let options = Options { bare_values: true, separator: Separator::Comma,
+                                ..Options::default() };
+        let written = to_string_with_options(&atom, options).unwrap();
+        assert_eq!(written, "[\n  production,\n  -x,\n  a.b,\n  \"true\",\n  \"-1x\",\n  \
+                             \"-.5\",\n  \"b c\",\n  \"NaN\"\n]\n");
+        let options = reader::Options { bare_values: true, nan_infinity: true, json5: true,
+                                        ..reader::Options::default() };
+        assert_eq!(Reader::with_options(&mut written.as_bytes(), options).parse_document()
+                       .unwrap(), atom);
+
+        let atom = repr::Object(vec![(repr::Key::new("a.b"), I64(1)), (repr::Key::new("c"), I64(2))]
+                                    .into_iter().collect());
+        let written = to_string(&atom).unwrap();
+        assert_eq!(written, "\"a.b\": 1\nc: 2\n");
+        let options = reader::Options { dotted_keys: true, ..reader::Options::default() };
+        assert_eq!(Reader::with_options(&mut written.as_bytes(), options).parse_document()
+                       .unwrap(), atom);
+
+        let tagged = |tag: &str, value| repr::Tagged(tag.to_string(), Box::new(value));
+        let atom = repr::Object(vec![(repr::Key::new("since"),
+                                      tagged("date", String("2024-01-01"))),
+                                     (repr::Key::new("x"), tagged("a", tagged("b", repr::Array(
+                                          vec![I64(1)]))))]
+                                    .into_iter().collect());
+        let written = to_string(&atom).unwrap();
+        assert_eq!(written, "since: @date \"2024-01-01\"\nx: @a @b [\n  1\n]\n");
+        let options = reader::Options { tags: true, ..reader::Options::default() };
+        assert_eq!(Reader::with_options(&mut written.as_bytes(), options).parse_document()
+                       .unwrap(), atom);
+        assert!(to_string(&repr::Array(vec![tagged("not bare", Null)])).is_err());
+    }
+}